@@ -1,162 +1,870 @@
 use crate::{
-    cache::Cache, lesswrong::PostWithComments, sort_comments::sort_comments_by_score_depth_first,
-};
-use anyhow::Result;
-use lesswrong_api::{Comment, Post};
-use openai::{
-    chat::{
-        ChatCompletion, ChatCompletionChoice, ChatCompletionGeneric, ChatCompletionMessage,
-        ChatCompletionMessageRole, VeniceParameters,
+    cache::Cache,
+    chunking::{
+        estimate_tokens, estimate_tokens_for_words, pack_chunks, paragraphs, sections,
+        truncate_to_tokens,
     },
-    Credentials, OpenAiError,
+    lesswrong::PostWithComments,
+    prompts::{Prompts, MAX_REVIEW_QUESTIONS, MAX_TAKEAWAYS},
+    retry::{RateLimiter, RetryPolicy},
+    sort_comments::CommentSelector,
+    summarizer::{ResponseFormat, Summarizer},
+    usage::{TokenUsage, UsageRecord},
 };
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use lesswrong_api::{Comment, Post};
 use regex::Regex;
-use std::{collections::HashMap, env};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, fmt, path::Path, sync::Mutex};
 
 pub struct AnnotatedPostWithComments {
     pub post: Post,
     pub comments: HashMap<String, Comment>,
     pub post_summary: String,
     pub comments_summary: String,
+    pub key_takeaways: KeyTakeaways,
+    pub review_questions: Vec<ReviewQuestion>,
 }
 
 impl AnnotatedPostWithComments {
-    pub fn new(post: PostWithComments, post_summary: String, comments_summary: String) -> Self {
+    pub fn new(
+        post: PostWithComments,
+        post_summary: String,
+        comments_summary: String,
+        key_takeaways: KeyTakeaways,
+        review_questions: Vec<ReviewQuestion>,
+    ) -> Self {
         Self {
             post: post.post,
             comments: post.comments,
             post_summary,
             comments_summary,
+            key_takeaways,
+            review_questions,
         }
     }
 }
 
+/// A term a post introduces or defines, collected into the book's glossary
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GlossaryTerm {
+    pub term: String,
+    pub definition: String,
+}
+
+/// Structured output next to the prose summary, empty if disabled or unavailable
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct KeyTakeaways {
+    pub takeaways: Vec<String>,
+    #[serde(default)]
+    pub glossary: Vec<GlossaryTerm>,
+}
+
+/// A question with its answer for reviewing a post, also exported as a flashcard
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReviewQuestion {
+    pub question: String,
+    pub answer: String,
+}
+
+#[derive(Deserialize)]
+struct ReviewQuestions {
+    questions: Vec<ReviewQuestion>,
+}
+
+/// The JSON object in a response, tolerating text or code fences around it
+fn json_object(response: &str) -> Result<&str> {
+    match (response.find('{'), response.rfind('}')) {
+        (Some(start), Some(end)) if start < end => Ok(&response[start..=end]),
+        _ => Err(anyhow::anyhow!("no JSON object in response")),
+    }
+}
+
+/// Parses the JSON answer to the key takeaways prompt
+fn parse_key_takeaways(response: &str) -> Result<KeyTakeaways> {
+    let mut key_takeaways: KeyTakeaways =
+        serde_json::from_str(json_object(response)?).context("invalid key takeaways JSON")?;
+
+    key_takeaways.takeaways.retain(|t| !t.trim().is_empty());
+    key_takeaways.takeaways.truncate(MAX_TAKEAWAYS);
+    key_takeaways
+        .glossary
+        .retain(|g| !g.term.trim().is_empty() && !g.definition.trim().is_empty());
+    if key_takeaways.takeaways.is_empty() {
+        return Err(anyhow::anyhow!("no key takeaways in response"));
+    }
+    Ok(key_takeaways)
+}
+
 fn remove_think_tags(input: &str) -> String {
     // (?is) makes the regex case-insensitive and `.` also matches newlines
     let re = Regex::new(r"(?is)<think[^>]*?>.*?</think[^>]*?>").unwrap();
     re.replace_all(input, "").to_string()
 }
 
-pub struct AiClient {
-    cache_post: Cache<String>,
-    cache_comments: Cache<String>,
-    credentials: Credentials,
-    model: String,
+// the prompts ask for about `word_budget` words, models overshoot that but not by this much
+const MAX_SUMMARY_LENGTH_FACTOR: usize = 3;
+
+// openings of responses where the model declined to summarize
+const REFUSAL_PREFIXES: &[&str] = &[
+    "i'm sorry",
+    "i am sorry",
+    "i can't",
+    "i cannot",
+    "i'm unable",
+    "i am unable",
+    "as an ai",
+];
+
+/// Checks a markdown response (with the think tags already removed) before it is used and cached
+fn validate_markdown(response: &str) -> Result<()> {
+    if response.is_empty() {
+        return Err(anyhow::anyhow!("empty response"));
+    }
+    // reasoning that remove_think_tags couldn't strip, e.g. unclosed or differently named tags
+    let reasoning_tag =
+        Regex::new(r"(?i)</?(think|thinking|reasoning|reflection)\b[^>]*>").unwrap();
+    if let Some(tag) = reasoning_tag.find(response) {
+        return Err(anyhow::anyhow!("leftover reasoning tag {}", tag.as_str()));
+    }
+    if let Some(line) = unclosed_code_fence(response) {
+        return Err(anyhow::anyhow!("unclosed code fence on line {}", line));
+    }
+    Ok(())
 }
 
-impl Default for AiClient {
-    fn default() -> Self {
-        let credentials = Credentials::from_env();
+/// Line number of a ``` or ~~~ code fence that is never closed, which would turn the rest of the page into code
+fn unclosed_code_fence(markdown: &str) -> Option<usize> {
+    // the fence character, its length and the line of the open fence
+    let mut open: Option<(char, usize, usize)> = None;
+    for (number, line) in markdown.lines().enumerate() {
+        let line = line.trim_start();
+        let Some(fence_char) = line.chars().next().filter(|c| *c == '`' || *c == '~') else {
+            continue;
+        };
+        let length = line.chars().take_while(|c| *c == fence_char).count();
+        if length < 3 {
+            continue;
+        }
+        match open {
+            None => open = Some((fence_char, length, number + 1)),
+            // a closing fence is at least as long as the open one and has no info string
+            Some((open_char, open_length, _))
+                if fence_char == open_char
+                    && length >= open_length
+                    && line[length..].trim().is_empty() =>
+            {
+                open = None
+            }
+            Some(_) => {}
+        }
+    }
+    open.map(|(_, _, line)| line)
+}
 
-        Self {
-            cache_post: Cache::new("ai-posts"),
-            cache_comments: Cache::new("ai-comments"),
-            credentials,
-            model: env::var("OPENAI_MODEL").unwrap_or_else(|_| panic!("OPENAI_MODEL not set")),
+/// Checks a summary like `validate_markdown`, and that it's neither too long nor a refusal
+fn validate_summary(summary: &str, max_words: usize) -> Result<()> {
+    validate_markdown(summary)?;
+    let words = summary.split_whitespace().count();
+    if words > max_words {
+        return Err(anyhow::anyhow!(
+            "response too long ({} words, at most {} expected)",
+            words,
+            max_words
+        ));
+    }
+    let lowercase = summary.to_lowercase();
+    if REFUSAL_PREFIXES
+        .iter()
+        .any(|prefix| lowercase.starts_with(prefix))
+    {
+        return Err(anyhow::anyhow!(
+            "model refused: {}",
+            truncate_to_tokens(summary, 20)
+        ));
+    }
+    Ok(())
+}
+
+/// Parses the JSON answer to the review questions prompt
+fn parse_review_questions(response: &str) -> Result<Vec<ReviewQuestion>> {
+    let ReviewQuestions { mut questions } =
+        serde_json::from_str(json_object(response)?).context("invalid review questions JSON")?;
+
+    questions.retain(|q| !q.question.trim().is_empty() && !q.answer.trim().is_empty());
+    questions.truncate(MAX_REVIEW_QUESTIONS);
+    if questions.is_empty() {
+        return Err(anyhow::anyhow!("no review questions in response"));
+    }
+    Ok(questions)
+}
+
+/// Everything a cached summary depends on, stored next to the summary
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SummaryMetadata {
+    pub post_id: String,
+    pub model: String,
+    /// hash of the rendered system prompt, changes whenever a prompt template or its variables change
+    pub prompt_hash: String,
+    pub parameters: Value,
+    pub created_at: DateTime<Utc>,
+    /// tokens billed for the request, missing for summaries cached before usage was tracked
+    #[serde(default)]
+    pub usage: Option<TokenUsage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedSummary {
+    pub summary: String,
+    pub metadata: SummaryMetadata,
+}
+
+// tokens of the context window kept free for the response, including the reasoning of thinking models.
+// `MIN_CONTEXT_TOKENS` leaves room for the prompt and the post beyond it
+const RESPONSE_TOKEN_RESERVE: usize = 4_096;
+
+// size of the parts of a post translated at once, the translation has to fit into the response
+const TRANSLATION_CHUNK_TOKENS: usize = 2_000;
+
+fn sha256_hex(input: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(input.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Cache key of a summary: the post ID plus a hash of the content, model, prompt and parameters.
+/// Changing any of them creates a new cache entry while keeping the old variants.
+fn summary_cache_key(
+    post_id: &str,
+    model: &str,
+    parameters: &Value,
+    system_prompt: &str,
+    user_message: &str,
+) -> String {
+    let hash = sha256_hex(&json!([user_message, model, system_prompt, parameters]).to_string());
+    format!("{}-{}", post_id, &hash[..16])
+}
+
+/// What a summary is about, posts and comments summaries are cached separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SummaryKind {
+    Post,
+    Comments,
+    /// key takeaways and glossary terms of a post as JSON
+    Takeaways,
+    /// review questions with answers as JSON
+    Questions,
+    /// a part of the post body translated into the book's language
+    Translation,
+}
+
+impl SummaryKind {
+    /// Description of the summarized text, used in the prompt combining partial summaries
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Post => "text",
+            Self::Comments => "comment discussion",
+            Self::Takeaways => "key takeaways",
+            Self::Questions => "review questions",
+            Self::Translation => "translation",
         }
     }
+
+    /// Separator between the units (paragraphs or comments) of the summarized text
+    fn separator(&self) -> &'static str {
+        match self {
+            Self::Post => "\n\n",
+            Self::Comments => "\n",
+            Self::Takeaways | Self::Questions | Self::Translation => "\n\n",
+        }
+    }
+
+    fn response_format(&self) -> ResponseFormat {
+        match self {
+            Self::Takeaways | Self::Questions => ResponseFormat::Json,
+            Self::Post | Self::Comments | Self::Translation => ResponseFormat::Text,
+        }
+    }
+}
+
+impl fmt::Display for SummaryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Post => "post",
+            Self::Comments => "comments",
+            Self::Takeaways => "key takeaways",
+            Self::Questions => "review questions",
+            Self::Translation => "translation",
+        })
+    }
+}
+
+/// Placeholder for summaries that still failed after all retries
+pub const SUMMARY_UNAVAILABLE: &str = "*Summary unavailable.*";
+
+/// A summary that was replaced by `SUMMARY_UNAVAILABLE`, or key takeaways that were left out
+#[derive(Debug, Clone)]
+pub struct DegradedSummary {
+    pub post_id: String,
+    pub post_title: String,
+    pub kind: SummaryKind,
+    pub error: String,
+}
+
+/// A summary to create: the message for a single request, and the units it is split into if it's too long
+struct SummaryRequest {
+    kind: SummaryKind,
+    system_prompt: String,
+    message: String,
+    units: Vec<String>,
+    /// wrapped around every chunk of units, e.g. to give the comments the post as context
+    chunk_prefix: String,
+    chunk_suffix: String,
+}
+
+impl SummaryRequest {
+    fn wrap_chunk(&self, chunk: &str) -> String {
+        format!("{}{}{}", self.chunk_prefix, chunk, self.chunk_suffix)
+    }
+}
+
+pub struct AiClient {
+    cache_post: Cache<CachedSummary>,
+    cache_comments: Cache<CachedSummary>,
+    cache_takeaways: Cache<CachedSummary>,
+    cache_questions: Cache<CachedSummary>,
+    cache_translations: Cache<CachedSummary>,
+    summarizer: Box<dyn Summarizer>,
+    prompts: Prompts,
+    retry_policy: RetryPolicy,
+    rate_limiter: Option<RateLimiter>,
+    degraded: Mutex<Vec<DegradedSummary>>,
+    usage: Mutex<Vec<UsageRecord>>,
+    key_takeaways: bool,
+    review_questions: bool,
+    translate_body: bool,
+    comment_selector: CommentSelector,
 }
 
 impl AiClient {
-    async fn create_summarize_post_completion(
+    pub fn new(summarizer: Box<dyn Summarizer>, prompts: Prompts) -> Self {
+        Self::with_cache_directory(summarizer, prompts, Path::new(".cache"))
+    }
+
+    /// Caches the summaries below `directory` instead of `.cache`, e.g. a temporary directory for tests
+    pub fn with_cache_directory(
+        summarizer: Box<dyn Summarizer>,
+        prompts: Prompts,
+        directory: &Path,
+    ) -> Self {
+        Self {
+            cache_post: Cache::in_directory(directory.join("ai-posts")),
+            cache_comments: Cache::in_directory(directory.join("ai-comments")),
+            cache_takeaways: Cache::in_directory(directory.join("ai-takeaways")),
+            cache_questions: Cache::in_directory(directory.join("ai-questions")),
+            cache_translations: Cache::in_directory(directory.join("ai-translations")),
+            summarizer,
+            prompts,
+            retry_policy: RetryPolicy::default(),
+            rate_limiter: None,
+            degraded: Mutex::new(vec![]),
+            usage: Mutex::new(vec![]),
+            key_takeaways: false,
+            review_questions: false,
+            translate_body: false,
+            comment_selector: CommentSelector::default(),
+        }
+    }
+
+    /// Also extract key takeaways and glossary terms of every post, one more request per post
+    pub fn set_key_takeaways(&mut self, enabled: bool) {
+        self.key_takeaways = enabled;
+    }
+
+    /// Also generate review questions with answers for every post, one more request per post
+    pub fn set_review_questions(&mut self, enabled: bool) {
+        self.review_questions = enabled;
+    }
+
+    /// Also translate the post bodies into the language of the prompts, see `Prompts::set_language`
+    pub fn set_translate_body(&mut self, enabled: bool) {
+        self.translate_body = enabled;
+    }
+
+    /// Which comments are summarized, the best 100 by score by default
+    pub fn set_comment_selector(&mut self, comment_selector: CommentSelector) {
+        self.comment_selector = comment_selector;
+    }
+
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
+
+    /// Limits the requests sent to the LLM provider, `None` sends them as fast as the jobs allow
+    pub fn set_requests_per_minute(&mut self, requests_per_minute: Option<usize>) {
+        self.rate_limiter = requests_per_minute.map(RateLimiter::new);
+    }
+
+    /// Summaries that failed so far, see `DegradedSummary`
+    pub fn degraded_summaries(&self) -> Vec<DegradedSummary> {
+        self.degraded.lock().unwrap().clone()
+    }
+
+    fn record_degraded(&self, kind: SummaryKind, post: &Post, error: anyhow::Error) {
+        println!(
+            "Failed to create the {} summary of {}: {:#}",
+            kind, post.title, error
+        );
+        self.degraded.lock().unwrap().push(DegradedSummary {
+            post_id: post.id.clone(),
+            post_title: post.title.clone(),
+            kind,
+            error: format!("{:#}", error),
+        });
+    }
+
+    /// Records a failed summary and returns the placeholder used instead
+    fn degrade(&self, kind: SummaryKind, post: &Post, error: anyhow::Error) -> String {
+        self.record_degraded(kind, post, error);
+        SUMMARY_UNAVAILABLE.to_string()
+    }
+
+    fn cache(&self, kind: SummaryKind) -> &Cache<CachedSummary> {
+        match kind {
+            SummaryKind::Post => &self.cache_post,
+            SummaryKind::Comments => &self.cache_comments,
+            SummaryKind::Takeaways => &self.cache_takeaways,
+            SummaryKind::Questions => &self.cache_questions,
+            SummaryKind::Translation => &self.cache_translations,
+        }
+    }
+
+    /// Key of the summary cache entry for a request, see `summary_cache_key`
+    fn cache_key(&self, post_id: &str, system_prompt: &str, user_message: &str) -> String {
+        summary_cache_key(
+            post_id,
+            self.summarizer.model(),
+            &self.summarizer.parameters(),
+            system_prompt,
+            user_message,
+        )
+    }
+
+    fn record_usage(&self, kind: SummaryKind, post: &Post, usage: TokenUsage, cached: bool) {
+        self.usage.lock().unwrap().push(UsageRecord {
+            post_id: post.id.clone(),
+            post_title: post.title.clone(),
+            kind,
+            usage,
+            cached,
+        });
+    }
+
+    /// Token usage of all summary requests so far, including the ones answered from the cache
+    pub fn usage_records(&self) -> Vec<UsageRecord> {
+        self.usage.lock().unwrap().clone()
+    }
+
+    pub fn model(&self) -> &str {
+        self.summarizer.model()
+    }
+
+    async fn complete_cached(
         &self,
+        kind: SummaryKind,
         post: &Post,
-    ) -> Result<ChatCompletionGeneric<ChatCompletionChoice>, OpenAiError> {
-        let post_messages = vec![
-        ChatCompletionMessage {
-            role: ChatCompletionMessageRole::System,
-            content: Some(
-                "You are an expert of distilling complex rationalist topics to a concise summary. Approach topics with an intellectual but approachable tone, NOT USING LISTS UNLESS NECESSARY and strategically to organize complex ideas. Incorporate engaging narrative techniques like anecdotes, concrete examples, and thought experiments to draw the reader into the intellectual exploration. Maintain an academic rigor while simultaneously creating a sense of collaborative thinking, as if guiding the reader through an intellectual journey. Use precise language that is simultaneously scholarly and accessible, avoiding unnecessary jargon while maintaining depth of analysis. Don't waste too many words with framing and setup. Optimize for quick readability and depth. Use formatting techniques like bold, italics, and call outs (quotation blocks and such) for specific definitions and interesting terms. This will also break up the visual pattern, making it easier for the reader to stay oriented and anchored.  Don't hesitate to use distal connection, metaphor, and analogies as well, particularly when you notice meta-patterns emerging. A good metaphor is the pinnacle of Coherence. Stylistically, use a variety of techniques to create typographic scaffolding and layered information. With this in mind, summarize the main points of the following LessWrong article keeping it under about 200 words: DO NOT BE REPETITIVE.".to_string(),
-            ),
-            ..Default::default()
-        },
-        ChatCompletionMessage {
-            role: ChatCompletionMessageRole::User,
-            content: Some(post.content_markdown.clone()),
-            ..Default::default()
-        },
-    ];
-        let post_completion = ChatCompletion::builder(&self.model, post_messages)
-            .venice_parameters(VeniceParameters {
-                include_venice_system_prompt: false,
+        system_prompt: &str,
+        user_message: &str,
+    ) -> Result<String> {
+        let model = self.summarizer.model();
+        let key = self.cache_key(&post.id, system_prompt, user_message);
+        let cache = self.cache(kind);
+        if let Some(cached) = cache.get(&key)? {
+            if let Some(usage) = cached.metadata.usage {
+                self.record_usage(kind, post, usage, true);
+            }
+            return Ok(cached.summary);
+        }
+
+        // invalid responses are retried like failed requests and never cached
+        let max_words = self.prompts.word_budget() * MAX_SUMMARY_LENGTH_FACTOR;
+        let description = format!("{} summary request for {}", kind, post.id);
+        let (response, usage) = self
+            .retry_policy
+            .retry(&description, || async {
+                if let Some(rate_limiter) = &self.rate_limiter {
+                    rate_limiter.acquire().await;
+                }
+                let completion = self
+                    .summarizer
+                    .complete(system_prompt, user_message, kind.response_format())
+                    .await?;
+                // rejected responses are billed as well
+                if let Some(usage) = completion.usage {
+                    self.record_usage(kind, post, usage, false);
+                }
+                let response = remove_think_tags(&completion.text).trim().to_string();
+                match kind {
+                    SummaryKind::Takeaways => parse_key_takeaways(&response).map(|_| ()),
+                    SummaryKind::Questions => parse_review_questions(&response).map(|_| ()),
+                    SummaryKind::Translation => validate_markdown(&response),
+                    SummaryKind::Post | SummaryKind::Comments => {
+                        validate_summary(&response, max_words)
+                    }
+                }
+                .with_context(|| format!("Invalid response from {}", model))?;
+                Ok((response, completion.usage))
             })
-            .credentials(self.credentials.clone())
-            .create();
+            .await?;
+
+        cache.set(
+            &key,
+            &CachedSummary {
+                summary: response.clone(),
+                metadata: SummaryMetadata {
+                    post_id: post.id.clone(),
+                    model: model.to_string(),
+                    prompt_hash: sha256_hex(system_prompt),
+                    parameters: self.summarizer.parameters(),
+                    created_at: Utc::now(),
+                    usage,
+                },
+            },
+        )?;
+        Ok(response)
+    }
+
+    /// Tokens of the context window left for the user message
+    fn input_token_budget(&self, system_prompt: &str) -> usize {
+        self.summarizer
+            .context_tokens()
+            .saturating_sub(estimate_tokens(system_prompt) + RESPONSE_TOKEN_RESERVE)
+    }
 
-        post_completion.await
+    /// The chunks the request's units are packed into if the message doesn't fit into the model's context
+    fn chunks(&self, request: &SummaryRequest) -> Option<Vec<String>> {
+        let budget = self.input_token_budget(&request.system_prompt);
+        if estimate_tokens(&request.message) <= budget {
+            return None;
+        }
+        let chunks = pack_chunks(
+            &request.units,
+            budget.saturating_sub(estimate_tokens(&request.wrap_chunk(""))),
+            request.kind.separator(),
+        );
+        Some(
+            chunks
+                .iter()
+                .map(|chunk| request.wrap_chunk(chunk))
+                .collect(),
+        )
     }
 
-    async fn create_summarize_comments_completion(
+    /// Summarizes the request's message in one request if it fits into the model's context.
+    /// Otherwise its units are packed into chunks that are summarized on their own,
+    /// and the partial summaries are combined (map-reduce).
+    async fn summarize_in_chunks(&self, post: &Post, request: &SummaryRequest) -> Result<String> {
+        let kind = request.kind;
+        let chunks = match self.chunks(request) {
+            None => {
+                return self
+                    .complete_cached(kind, post, &request.system_prompt, &request.message)
+                    .await
+            }
+            Some(chunks) => chunks,
+        };
+
+        println!(
+            "The {} of {} is too long for {}, summarizing it in {} parts",
+            kind.label(),
+            post.title,
+            self.summarizer.model(),
+            chunks.len()
+        );
+        let mut summaries = Vec::with_capacity(chunks.len());
+        for chunk in chunks {
+            summaries.push(
+                self.complete_cached(kind, post, &request.system_prompt, &chunk)
+                    .await?,
+            );
+        }
+
+        self.combine_summaries(kind, post, summaries).await
+    }
+
+    /// Combines partial summaries into one. If they don't fit into the context together, they are combined in groups first
+    async fn combine_summaries(
         &self,
-        post: &PostWithComments,
-    ) -> Result<ChatCompletionGeneric<ChatCompletionChoice>, OpenAiError> {
-        let comments = sort_comments_by_score_depth_first(&post.comments, 100);
+        kind: SummaryKind,
+        post: &Post,
+        mut summaries: Vec<String>,
+    ) -> Result<String> {
+        loop {
+            let system_prompt =
+                self.prompts
+                    .combine_summaries(post, kind.label(), summaries.len())?;
+            let chunks = pack_chunks(&summaries, self.input_token_budget(&system_prompt), "\n\n");
+            if chunks.len() == 1 {
+                return self
+                    .complete_cached(kind, post, &system_prompt, &chunks[0])
+                    .await;
+            }
+            if chunks.len() >= summaries.len() {
+                return Err(anyhow::anyhow!(
+                    "The partial summaries of {} don't fit into the context of {}",
+                    post.title,
+                    self.summarizer.model()
+                ));
+            }
+
+            let mut combined = Vec::with_capacity(chunks.len());
+            for chunk in chunks {
+                combined.push(
+                    self.complete_cached(kind, post, &system_prompt, &chunk)
+                        .await?,
+                );
+            }
+            summaries = combined;
+        }
+    }
+
+    fn post_request(&self, post: &Post) -> Result<SummaryRequest> {
+        Ok(SummaryRequest {
+            kind: SummaryKind::Post,
+            system_prompt: self.prompts.summarize_post(post)?,
+            message: post.content_markdown.clone(),
+            units: paragraphs(&post.content_markdown),
+            chunk_prefix: String::new(),
+            chunk_suffix: String::new(),
+        })
+    }
+
+    fn comments_request(&self, post: &PostWithComments) -> Result<SummaryRequest> {
+        let comments = self
+            .comment_selector
+            .select(&post.comments, &post.post.author);
+        let system_prompt = self
+            .prompts
+            .summarize_comments(&post.post, comments.len())?;
         let comments = comments
             .iter()
             .map(|c| {
-                let score = c.base_score;
-                let content = c.content_markdown.clone();
+                let score = c.comment.base_score;
+                let content = c.comment.content_markdown.clone();
                 format!("<comment><score>{}</score>: {}</comment>", score, content).to_string()
             })
-            .collect::<Vec<String>>()
-            .join("\n");
-
-        let post_messages = vec![
-        ChatCompletionMessage {
-            role: ChatCompletionMessageRole::System,
-            content: Some(
-                "You are an expert of distilling complex rationalist topics to a concise summary. Approach topics with an intellectual but approachable tone, NOT USING LISTS UNLESS NECESSARY and strategically to organize complex ideas. Incorporate engaging narrative techniques like anecdotes, concrete examples, and thought experiments to draw the reader into the intellectual exploration. Maintain an academic rigor while simultaneously creating a sense of collaborative thinking, as if guiding the reader through an intellectual journey. Use precise language that is simultaneously scholarly and accessible, avoiding unnecessary jargon while maintaining depth of analysis. Don't waste too many words with framing and setup. Optimize for quick readability and depth. Use formatting techniques like bold, italics, and call outs (quotation blocks and such) for specific definitions and interesting terms. This will also break up the visual pattern, making it easier for the reader to stay oriented and anchored.  Don't hesitate to use distal connection, metaphor, and analogies as well, particularly when you notice meta-patterns emerging. A good metaphor is the pinnacle of Coherence. Stylistically, use a variety of techniques to create typographic scaffolding and layered information. With this in mind, summarize THE DISCUSSION IN THE COMMENTS presented here keeping it under about 200 words. A comment can contain a score, give more importance to higher scores BUT DO NOT EXPLICITLY MENTION THE SCORES. Comments can also be replies to previous comments, all comments are provided depth-first. DO NOT BE REPETITIVE. DO NOT SUMMARIZE THE POST ITSELF, IT IS ONLY PROVIDED AS CONTEXT.".to_string(),
-            ),
-            ..Default::default()
-        },
-        ChatCompletionMessage {
-            role: ChatCompletionMessageRole::User,
-            content: Some(format!("<post>{}</post><comments>{}</comments>", post.post.content_markdown.clone(), comments)),
-            ..Default::default()
-        },
-    ];
-        let post_completion = ChatCompletion::builder(&self.model, post_messages)
-            .venice_parameters(VeniceParameters {
-                include_venice_system_prompt: false,
-            })
-            .credentials(self.credentials.clone())
-            .create();
+            .collect::<Vec<String>>();
+        let message = format!(
+            "<post>{}</post><comments>{}</comments>",
+            post.post.content_markdown,
+            comments.join("\n")
+        );
+
+        // the post is only context for the comments, so it can be cut short when the comments are split up
+        let post_context = truncate_to_tokens(
+            &post.post.content_markdown,
+            self.input_token_budget(&system_prompt) / 2,
+        );
+        Ok(SummaryRequest {
+            kind: SummaryKind::Comments,
+            chunk_prefix: format!("<post>{}</post><comments>", post_context),
+            chunk_suffix: "</comments>".to_string(),
+            system_prompt,
+            message,
+            units: comments,
+        })
+    }
 
-        post_completion.await
+    /// Request for structured output about the post, the key takeaways or review questions
+    fn structured_request(&self, kind: SummaryKind, post: &Post) -> Result<SummaryRequest> {
+        let system_prompt = match kind {
+            SummaryKind::Questions => self.prompts.review_questions(post)?,
+            _ => self.prompts.key_takeaways(post)?,
+        };
+        // structured output can't be combined from parts, long posts are cut short instead
+        let message = truncate_to_tokens(
+            &post.content_markdown,
+            self.input_token_budget(&system_prompt),
+        )
+        .to_string();
+        Ok(SummaryRequest {
+            kind,
+            system_prompt,
+            message,
+            units: vec![],
+            chunk_prefix: String::new(),
+            chunk_suffix: String::new(),
+        })
     }
 
+    /// Summarizes the post, falling back to `SUMMARY_UNAVAILABLE` if the LLM keeps failing.
+    /// Only errors unrelated to the LLM, like broken prompt templates, are returned
     pub async fn summarize_post(&self, post: &Post) -> Result<String> {
-        if let Some(cached) = self.cache_post.get(&post.id)? {
-            return Ok(cached);
+        let request = self.post_request(post)?;
+        let summary = self.summarize_in_chunks(post, &request).await;
+        Ok(summary.unwrap_or_else(|e| self.degrade(SummaryKind::Post, post, e)))
+    }
+
+    /// Summarizes the comments, falling back to `SUMMARY_UNAVAILABLE` like `summarize_post`
+    pub async fn summarize_comments(&self, post: &PostWithComments) -> Result<String> {
+        let request = self.comments_request(post)?;
+        let summary = self.summarize_in_chunks(&post.post, &request).await;
+        Ok(summary.unwrap_or_else(|e| self.degrade(SummaryKind::Comments, &post.post, e)))
+    }
+
+    /// Key takeaways and glossary terms of the post if enabled with `set_key_takeaways`.
+    /// Empty if disabled or if the LLM keeps failing
+    pub async fn key_takeaways(&self, post: &Post) -> Result<KeyTakeaways> {
+        if !self.key_takeaways {
+            return Ok(KeyTakeaways::default());
         }
-        let request = self.create_summarize_post_completion(post).await?;
+        let request = self.structured_request(SummaryKind::Takeaways, post)?;
+        let response = self
+            .complete_cached(
+                SummaryKind::Takeaways,
+                post,
+                &request.system_prompt,
+                &request.message,
+            )
+            .await
+            .and_then(|response| parse_key_takeaways(&response));
+        Ok(response.unwrap_or_else(|e| {
+            self.record_degraded(SummaryKind::Takeaways, post, e);
+            KeyTakeaways::default()
+        }))
+    }
 
-        let response = request.choices[0]
-            .message
-            .content
-            .clone()
-            .unwrap_or_default();
-        let response = remove_think_tags(&response).trim().to_string();
+    /// Review questions with answers about the post if enabled with `set_review_questions`.
+    /// Empty if disabled or if the LLM keeps failing
+    pub async fn review_questions(&self, post: &Post) -> Result<Vec<ReviewQuestion>> {
+        if !self.review_questions {
+            return Ok(vec![]);
+        }
+        let request = self.structured_request(SummaryKind::Questions, post)?;
+        let response = self
+            .complete_cached(
+                SummaryKind::Questions,
+                post,
+                &request.system_prompt,
+                &request.message,
+            )
+            .await
+            .and_then(|response| parse_review_questions(&response));
+        Ok(response.unwrap_or_else(|e| {
+            self.record_degraded(SummaryKind::Questions, post, e);
+            vec![]
+        }))
+    }
 
-        self.cache_post.set(&post.id, &response)?;
-        Ok(response)
+    /// The parts of the post body that are translated separately: whole sections, as long as they fit into a response
+    fn translation_chunks(&self, post: &Post, system_prompt: &str) -> Vec<String> {
+        let max_tokens = TRANSLATION_CHUNK_TOKENS.min(self.input_token_budget(system_prompt));
+        pack_chunks(&sections(&post.content_markdown), max_tokens, "\n\n")
     }
 
-    pub async fn summarize_comments(&self, post: &PostWithComments) -> Result<String> {
-        if let Some(cached) = self.cache_comments.get(&post.post.id)? {
-            return Ok(cached);
+    /// The post body translated into the book's language if enabled with `set_translate_body`, otherwise unchanged.
+    /// Parts that can't be translated are kept in the original language
+    pub async fn translate_post(&self, post: &Post) -> Result<String> {
+        if !self.translate_body {
+            return Ok(post.content_markdown.clone());
+        }
+        let system_prompt = self.prompts.translate(post)?;
+        let mut translated = vec![];
+        for chunk in self.translation_chunks(post, &system_prompt) {
+            match self
+                .complete_cached(SummaryKind::Translation, post, &system_prompt, &chunk)
+                .await
+            {
+                Ok(translation) => translated.push(translation),
+                Err(e) => {
+                    self.record_degraded(SummaryKind::Translation, post, e);
+                    translated.push(chunk);
+                }
+            }
         }
-        let request = self.create_summarize_comments_completion(post).await?;
+        Ok(translated.join("\n\n"))
+    }
 
-        let response = request.choices[0]
-            .message
-            .content
-            .clone()
-            .unwrap_or_default();
-        let response = remove_think_tags(&response).trim().to_string();
+    /// Estimates the tokens the summaries of the post and its comments will use, without calling the LLM (`--dry-run`).
+    /// Requests already in the cache count as cached, the completions are assumed to be as long as the word budget
+    pub fn estimate_usage(&self, post: &PostWithComments) -> Result<Vec<UsageRecord>> {
+        let mut requests = vec![self.post_request(&post.post)?, self.comments_request(post)?];
+        if self.key_takeaways {
+            requests.push(self.structured_request(SummaryKind::Takeaways, &post.post)?);
+        }
+        if self.review_questions {
+            requests.push(self.structured_request(SummaryKind::Questions, &post.post)?);
+        }
+        let mut records = self.estimate_requests(&post.post, requests)?;
+        if self.translate_body {
+            // a translation is about as long as the original
+            let system_prompt = self.prompts.translate(&post.post)?;
+            for chunk in self.translation_chunks(&post.post, &system_prompt) {
+                let key = self.cache_key(&post.post.id, &system_prompt, &chunk);
+                records.push(UsageRecord {
+                    post_id: post.post.id.clone(),
+                    post_title: post.post.title.clone(),
+                    kind: SummaryKind::Translation,
+                    usage: TokenUsage {
+                        prompt_tokens: (estimate_tokens(&system_prompt) + estimate_tokens(&chunk))
+                            as u64,
+                        completion_tokens: estimate_tokens(&chunk) as u64,
+                    },
+                    cached: self.cache(SummaryKind::Translation).get(&key)?.is_some(),
+                });
+            }
+        }
+        Ok(records)
+    }
 
-        self.cache_comments.set(&post.post.id, &response)?;
-        Ok(response)
+    /// Estimates the tokens of the post summary alone like `estimate_usage`, for posts outside the book like referenced posts
+    pub fn estimate_post_usage(&self, post: &Post) -> Result<Vec<UsageRecord>> {
+        self.estimate_requests(post, vec![self.post_request(post)?])
+    }
+
+    fn estimate_requests(
+        &self,
+        post: &Post,
+        requests: Vec<SummaryRequest>,
+    ) -> Result<Vec<UsageRecord>> {
+        let completion_tokens = estimate_tokens_for_words(self.prompts.word_budget()) as u64;
+        let mut records = vec![];
+        for request in requests {
+            let chunks = self.chunks(&request);
+            let messages = chunks
+                .clone()
+                .unwrap_or_else(|| vec![request.message.clone()]);
+            let mut record = |system_prompt: &str, message: &str, cached: bool| {
+                records.push(UsageRecord {
+                    post_id: post.id.clone(),
+                    post_title: post.title.clone(),
+                    kind: request.kind,
+                    usage: TokenUsage {
+                        prompt_tokens: (estimate_tokens(system_prompt) + estimate_tokens(message))
+                            as u64,
+                        completion_tokens,
+                    },
+                    cached,
+                })
+            };
+            for message in &messages {
+                let key = self.cache_key(&post.id, &request.system_prompt, message);
+                let cached = self.cache(request.kind).get(&key)?.is_some();
+                record(&request.system_prompt, message, cached);
+            }
+            // the partial summaries are unknown before they are created, estimate a single combining request
+            if let Some(chunks) = chunks {
+                let system_prompt =
+                    self.prompts
+                        .combine_summaries(post, request.kind.label(), chunks.len())?;
+                let summaries = "word ".repeat(self.prompts.word_budget() * chunks.len());
+                record(&system_prompt, &summaries, false);
+            }
+        }
+        Ok(records)
     }
 }
 
@@ -175,3 +883,115 @@ fn test_remove_think_tags() {
         assert_eq!(remove_think_tags(input), expected);
     }
 }
+
+#[test]
+fn test_validate_summary() {
+    assert!(validate_summary("A **valid** summary.\n\n- with a list", 100).is_ok());
+
+    assert!(validate_summary("", 100).is_err());
+    assert!(validate_summary(&"word ".repeat(101), 100).is_err());
+    assert!(validate_summary("<think>unclosed reasoning\n\nThe summary", 100).is_err());
+    assert!(validate_summary("The summary</reasoning>", 100).is_err());
+    assert!(validate_summary("Code:\n\n````rust\nlet x = 1;\n```\n", 100).is_err());
+    assert!(validate_summary("Code:\n\n  ```rust\n  let x = 1;\n  ```", 100).is_ok());
+    assert!(validate_summary("I'm sorry, but I can't help with that.", 100).is_err());
+}
+
+#[test]
+fn test_parse_key_takeaways() -> Result<()> {
+    let response = r#"```json
+{"takeaways": ["Beliefs should pay rent in anticipated experiences.", ""],
+ "glossary": [{"term": "Floating belief", "definition": "A belief that constrains no experience."}, {"term": "", "definition": "x"}]}
+```"#;
+    let key_takeaways = parse_key_takeaways(response)?;
+
+    assert_eq!(
+        key_takeaways.takeaways,
+        vec!["Beliefs should pay rent in anticipated experiences."]
+    );
+    assert_eq!(key_takeaways.glossary.len(), 1);
+    assert_eq!(key_takeaways.glossary[0].term, "Floating belief");
+
+    assert!(parse_key_takeaways(r#"{"takeaways": []}"#).is_err());
+    assert!(parse_key_takeaways("Here are the takeaways: none").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_parse_review_questions() -> Result<()> {
+    let questions = parse_review_questions(
+        r#"{"questions": [{"question": "What is a floating belief?", "answer": "One that constrains no experience."}, {"question": "", "answer": "x"}]}"#,
+    )?;
+
+    assert_eq!(
+        questions,
+        vec![ReviewQuestion {
+            question: "What is a floating belief?".to_string(),
+            answer: "One that constrains no experience.".to_string(),
+        }]
+    );
+    assert!(parse_review_questions(r#"{"questions": []}"#).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_summary_cache_key() {
+    let parameters = json!({ "provider": "openai" });
+    let key = summary_cache_key("post", "model-a", &parameters, "prompt", "content");
+
+    assert!(key.starts_with("post-"));
+    assert_eq!(
+        key,
+        summary_cache_key("post", "model-a", &parameters, "prompt", "content")
+    );
+    assert_ne!(
+        key,
+        summary_cache_key("post", "model-b", &parameters, "prompt", "content")
+    );
+    assert_ne!(
+        key,
+        summary_cache_key("post", "model-a", &parameters, "new prompt", "content")
+    );
+    assert_ne!(
+        key,
+        summary_cache_key("post", "model-a", &json!({}), "prompt", "content")
+    );
+    assert_ne!(
+        key,
+        summary_cache_key("post", "model-a", &parameters, "prompt", "edited content")
+    );
+}
+
+#[test]
+fn test_estimate_usage() -> Result<()> {
+    let directory =
+        std::env::temp_dir().join(format!("epub-test-estimate-usage-{}", std::process::id()));
+    let ai = AiClient::with_cache_directory(
+        Box::new(crate::summarizer::StubSummarizer),
+        Prompts::default(),
+        &directory,
+    );
+    let post = PostWithComments {
+        post: Post {
+            id: "test-estimate-usage".to_string(),
+            title: "Estimates".to_string(),
+            content_markdown: "word ".repeat(1000),
+            ..Post::default()
+        },
+        comments: HashMap::new(),
+    };
+
+    let records = ai.estimate_usage(&post)?;
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].kind, SummaryKind::Post);
+    assert_eq!(records[1].kind, SummaryKind::Comments);
+    // the post text alone is 1250 tokens, the comments request includes it too
+    assert!(records
+        .iter()
+        .all(|r| r.usage.prompt_tokens > 1250 && !r.cached));
+    assert_eq!(
+        records[0].usage.completion_tokens,
+        estimate_tokens_for_words(200) as u64
+    );
+    Ok(())
+}
@@ -17,9 +17,13 @@ where
     T: Serialize + DeserializeOwned,
 {
     pub fn new(tag: &str) -> Self {
-        let directory = Path::new(".cache").join(tag);
+        Self::in_directory(Path::new(".cache").join(tag))
+    }
+
+    /// A cache outside of `.cache`, e.g. a temporary directory for tests
+    pub fn in_directory(directory: impl Into<PathBuf>) -> Self {
         Self {
-            directory,
+            directory: directory.into(),
             _phantom: std::marker::PhantomData,
         }
     }
@@ -0,0 +1,145 @@
+// rough average for English text, good enough to stay within a context budget
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Estimates the number of tokens of a text without a model specific tokenizer
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(CHARS_PER_TOKEN)
+}
+
+/// Estimates the number of tokens of a text with the given number of words
+pub fn estimate_tokens_for_words(words: usize) -> usize {
+    // about 4 tokens per 3 English words
+    (words * 4).div_ceil(3)
+}
+
+/// Cuts the text after roughly `max_tokens` tokens, at a word boundary
+pub fn truncate_to_tokens(text: &str, max_tokens: usize) -> &str {
+    let max_chars = max_tokens * CHARS_PER_TOKEN;
+    match text.char_indices().nth(max_chars) {
+        None => text,
+        Some((end, _)) => {
+            let end = text[..end].rfind(char::is_whitespace).unwrap_or(end);
+            &text[..end]
+        }
+    }
+}
+
+/// Splits a markdown text into its paragraphs
+pub fn paragraphs(text: &str) -> Vec<String> {
+    text.split("\n\n")
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Splits a markdown text into its sections, each starting at a heading
+pub fn sections(text: &str) -> Vec<String> {
+    let mut sections = vec![];
+    let mut current = String::new();
+    for line in text.lines() {
+        if line.starts_with('#') && !current.trim().is_empty() {
+            sections.push(current.trim().to_string());
+            current.clear();
+        }
+        current.push_str(line);
+        current.push('\n');
+    }
+    if !current.trim().is_empty() {
+        sections.push(current.trim().to_string());
+    }
+    sections
+}
+
+/// Greedily packs the units (paragraphs, comments, summaries) into as few chunks of at most `max_tokens` as possible,
+/// keeping their order. Units that are too long on their own are split at word boundaries.
+pub fn pack_chunks(units: &[String], max_tokens: usize, separator: &str) -> Vec<String> {
+    let max_tokens = max_tokens.max(1);
+    let mut chunks = vec![];
+    let mut current = String::new();
+
+    for unit in units.iter().flat_map(|unit| split_unit(unit, max_tokens)) {
+        let candidate = if current.is_empty() {
+            unit.to_string()
+        } else {
+            format!("{}{}{}", current, separator, unit)
+        };
+        if estimate_tokens(&candidate) > max_tokens && !current.is_empty() {
+            chunks.push(std::mem::replace(&mut current, unit.to_string()));
+        } else {
+            current = candidate;
+        }
+    }
+    if !current.is_empty() || chunks.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+fn split_unit(unit: &str, max_tokens: usize) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut rest = unit;
+    while estimate_tokens(rest) > max_tokens {
+        let part = truncate_to_tokens(rest, max_tokens);
+        // a single word longer than the budget, cut it anyway to make progress
+        let part = if part.is_empty() {
+            let end = rest
+                .char_indices()
+                .nth(max_tokens * CHARS_PER_TOKEN)
+                .map_or(rest.len(), |(i, _)| i);
+            &rest[..end]
+        } else {
+            part
+        };
+        parts.push(part);
+        rest = rest[part.len()..].trim_start();
+    }
+    if !rest.is_empty() {
+        parts.push(rest);
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_units_into_chunks_within_budget() {
+        let units = paragraphs("aaaa aaaa\n\nbbbb bbbb\n\ncccc cccc\n\n\n\ndddd");
+
+        // every paragraph is 3 tokens, two of them joined by "\n\n" are 5
+        let chunks = pack_chunks(&units, 5, "\n\n");
+        assert_eq!(chunks, vec!["aaaa aaaa\n\nbbbb bbbb", "cccc cccc\n\ndddd"]);
+        assert!(chunks.iter().all(|c| estimate_tokens(c) <= 5));
+
+        assert_eq!(pack_chunks(&units, 1000, "\n").len(), 1);
+        assert_eq!(pack_chunks(&[], 10, "\n"), vec![String::new()]);
+    }
+
+    #[test]
+    fn splits_units_longer_than_the_budget() {
+        let long = "word ".repeat(100);
+        let chunks = pack_chunks(&[long.trim().to_string()], 10, "\n");
+
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| estimate_tokens(c) <= 10));
+        assert_eq!(chunks.join(" "), long.trim());
+    }
+
+    #[test]
+    fn splits_sections_at_headings() {
+        let text = "Intro\n\n# First\n\nText\n## Second\nMore text\n";
+        assert_eq!(
+            sections(text),
+            vec!["Intro", "# First\n\nText", "## Second\nMore text"]
+        );
+        assert!(sections("").is_empty());
+    }
+
+    #[test]
+    fn truncates_at_word_boundaries() {
+        assert_eq!(truncate_to_tokens("short", 10), "short");
+        assert_eq!(truncate_to_tokens("one two three four", 2), "one two");
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, VecDeque},
+};
+
+use lesswrong_api::Comment;
+
+/// A comment reached by walking a `CommentTree`
+#[derive(Debug, Clone, Copy)]
+pub struct TreeNode<'a> {
+    pub comment: &'a Comment,
+    /// 0 for top-level comments
+    pub depth: usize,
+    /// the top-level comment of the thread
+    pub root: &'a Comment,
+}
+
+/// Comments indexed by their parent, built once in linear time and walked without recursion.
+/// Replies whose parent is missing from the comments are unreachable, as they were never shown in a thread
+pub struct CommentTree<'a> {
+    comments: Vec<&'a Comment>,
+    roots: Vec<usize>,
+    children: Vec<Vec<usize>>,
+}
+
+impl<'a> CommentTree<'a> {
+    pub fn new(comments: impl IntoIterator<Item = &'a Comment>) -> Self {
+        let comments = comments.into_iter().collect::<Vec<_>>();
+        let index = comments
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (c.id.as_str(), i))
+            .collect::<HashMap<_, _>>();
+        let mut roots = vec![];
+        let mut children = vec![vec![]; comments.len()];
+        for (i, comment) in comments.iter().enumerate() {
+            match &comment.parent_comment_id {
+                None => roots.push(i),
+                Some(parent) => {
+                    if let Some(&parent) = index.get(parent.as_str()) {
+                        children[parent].push(i);
+                    }
+                }
+            }
+        }
+        Self {
+            comments,
+            roots,
+            children,
+        }
+    }
+
+    /// Orders the top-level comments and the replies to every comment by `rank` descending, ties by id
+    pub fn sort_by_rank(&mut self, rank: impl Fn(&Comment) -> f64) {
+        let ranks = self.comments.iter().map(|c| rank(c)).collect::<Vec<_>>();
+        let comments = &self.comments;
+        let compare = |a: &usize, b: &usize| {
+            ranks[*b]
+                .partial_cmp(&ranks[*a])
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| comments[*a].id.cmp(&comments[*b].id))
+        };
+        self.roots.sort_by(compare);
+        for children in &mut self.children {
+            children.sort_by(compare);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.comments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.comments.is_empty()
+    }
+
+    pub fn roots(&self) -> impl Iterator<Item = &'a Comment> + '_ {
+        self.roots.iter().map(|&i| self.comments[i])
+    }
+
+    fn node(&self, i: usize, depth: usize, root: usize) -> TreeNode<'a> {
+        TreeNode {
+            comment: self.comments[i],
+            depth,
+            root: self.comments[root],
+        }
+    }
+
+    /// Every comment followed by its replies, i.e. threads in reading order
+    pub fn depth_first(&self) -> Vec<TreeNode<'a>> {
+        let mut nodes = Vec::with_capacity(self.len());
+        let mut stack = self
+            .roots
+            .iter()
+            .rev()
+            .map(|&i| (i, 0, i))
+            .collect::<Vec<_>>();
+        while let Some((i, depth, root)) = stack.pop() {
+            nodes.push(self.node(i, depth, root));
+            stack.extend(self.children[i].iter().rev().map(|&c| (c, depth + 1, root)));
+        }
+        nodes
+    }
+
+    /// All comments of one depth before the next, starting with the top-level comments
+    pub fn breadth_first(&self) -> Vec<TreeNode<'a>> {
+        let mut nodes = Vec::with_capacity(self.len());
+        let mut queue = self
+            .roots
+            .iter()
+            .map(|&i| (i, 0, i))
+            .collect::<VecDeque<_>>();
+        while let Some((i, depth, root)) = queue.pop_front() {
+            nodes.push(self.node(i, depth, root));
+            queue.extend(self.children[i].iter().map(|&c| (c, depth + 1, root)));
+        }
+        nodes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comment(id: &str, parent: Option<&str>, score: f64) -> Comment {
+        Comment {
+            id: id.into(),
+            parent_comment_id: parent.map(Into::into),
+            base_score: score,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn walks_sorted_tree_depth_and_breadth_first() {
+        let comments = [
+            comment("a", None, 1.0),
+            comment("b", None, 2.0),
+            comment("aa", Some("a"), 5.0),
+            comment("ab", Some("a"), 7.0),
+            comment("aba", Some("ab"), 1.0),
+            comment("orphan", Some("deleted"), 100.0),
+        ];
+        let mut tree = CommentTree::new(&comments);
+        tree.sort_by_rank(|c| c.base_score);
+
+        let ids = |nodes: Vec<TreeNode>| {
+            nodes
+                .iter()
+                .map(|n| (n.comment.id.clone(), n.depth, n.root.id.clone()))
+                .collect::<Vec<_>>()
+        };
+        let node = |id: &str, depth, root: &str| (id.to_string(), depth, root.to_string());
+        assert_eq!(
+            ids(tree.depth_first()),
+            vec![
+                node("b", 0, "b"),
+                node("a", 0, "a"),
+                node("ab", 1, "a"),
+                node("aba", 2, "a"),
+                node("aa", 1, "a"),
+            ]
+        );
+        assert_eq!(
+            ids(tree.breadth_first()),
+            vec![
+                node("b", 0, "b"),
+                node("a", 0, "a"),
+                node("ab", 1, "a"),
+                node("aa", 1, "a"),
+                node("aba", 2, "a"),
+            ]
+        );
+    }
+
+    #[test]
+    fn walks_deep_threads_without_recursion() {
+        let depth = 100_000;
+        let comments = (0..depth)
+            .map(|i| {
+                let parent = (i > 0).then(|| (i - 1).to_string());
+                comment(&i.to_string(), parent.as_deref(), 1.0)
+            })
+            .collect::<Vec<_>>();
+        let tree = CommentTree::new(&comments);
+
+        let nodes = tree.depth_first();
+        assert_eq!(nodes.len(), depth);
+        assert_eq!(nodes[depth - 1].depth, depth - 1);
+    }
+}
@@ -0,0 +1,88 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use handlebars::Handlebars;
+use serde_json::json;
+
+use crate::image_renderer::rasterize_svg_to_width;
+
+// the size Amazon recommends for Kindle covers
+const COVER_WIDTH: u32 = 1600;
+const COVER_HEIGHT: u32 = 2560;
+const TITLE_TOP: usize = 900;
+
+/// Font sizes of the title with the characters that fit on a line, the first one that fits the title into `MAX_TITLE_LINES` is used
+const TITLE_SIZES: [(usize, usize); 3] = [(140, 14), (110, 18), (85, 24)];
+const MAX_TITLE_LINES: usize = 4;
+
+/// Splits the text into lines of at most `max_chars` characters at spaces. Longer words get a line of their own
+fn wrap_text(text: &str, max_chars: usize) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    for word in text.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if line.chars().count() + 1 + word.chars().count() <= max_chars => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.to_string()),
+        }
+    }
+    lines
+}
+
+/// Renders a PNG cover with the title, authors and date onto the background of `epub_resources/cover.svg.hbs`.
+/// Runs locally, the text uses the system's serif font
+pub fn generate_cover(title: &str, authors: &[String], date: DateTime<Utc>) -> Result<Vec<u8>> {
+    let (title_size, title_lines) = TITLE_SIZES
+        .iter()
+        .map(|(size, max_chars)| (*size, wrap_text(title, *max_chars)))
+        .find(|(_, lines)| lines.len() <= MAX_TITLE_LINES)
+        .unwrap_or_else(|| {
+            let (size, max_chars) = TITLE_SIZES[TITLE_SIZES.len() - 1];
+            (size, wrap_text(title, max_chars))
+        });
+    let line_height = title_size * 5 / 4;
+    let title_lines = title_lines
+        .iter()
+        .enumerate()
+        .map(|(i, text)| json!({ "text": text, "y": TITLE_TOP + i * line_height }))
+        .collect::<Vec<_>>();
+    let rule_y = TITLE_TOP + title_lines.len() * line_height;
+
+    let template = std::fs::read_to_string("epub_resources/cover.svg.hbs")?;
+    let reg = Handlebars::new();
+    let svg = reg.render_template(
+        &template,
+        &json!({ "width": COVER_WIDTH, "height": COVER_HEIGHT, "title_lines": title_lines, "title_size": title_size, "rule_y": rule_y, "authors_y": rule_y + 160, "authors": authors.join(", "), "date": date.format("%B %Y").to_string() }),
+    )?;
+    rasterize_svg_to_width(svg.as_bytes(), COVER_WIDTH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_renderer::ImageFormat;
+
+    #[test]
+    fn wraps_title_at_spaces() {
+        assert_eq!(
+            wrap_text("The Lens That Sees Its Flaws", 16),
+            vec!["The Lens That", "Sees Its Flaws"]
+        );
+        assert_eq!(
+            wrap_text("Incomprehensibilities abound", 10),
+            vec!["Incomprehensibilities", "abound"]
+        );
+    }
+
+    #[test]
+    fn renders_cover_as_png() -> Result<()> {
+        let cover = generate_cover(
+            "A & <Very> Long Title That Needs Several Lines",
+            &["Author One".to_string(), "Author Two".to_string()],
+            Utc::now(),
+        )?;
+
+        assert_eq!(ImageFormat::sniff(&cover), Some(ImageFormat::Png));
+        Ok(())
+    }
+}
@@ -1,15 +1,33 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
 use epub_builder::{EpubContent, ReferenceType, ZipLibrary};
+use futures::{stream, StreamExt};
 use handlebars::Handlebars;
 use lesswrong_api::Post;
 use lol_html::{element, html_content::ContentType, rewrite_str, RewriteStrSettings};
+use regex::{Captures, Regex};
+use serde::Serialize;
 use serde_json::json;
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
 
 use crate::{
     ai::AnnotatedPostWithComments,
+    cover::generate_cover,
+    footnotes::link_footnotes,
     image_embedder::{EmbeddingResult, ImageEmbedder},
+    image_renderer::ImageFormat,
+    locale::Locale,
+    math::{markdown_to_html, render_math, MathRendering},
+    references::{add_heading_ids, linked_post_ids, rewrite_post_links, ReferencedPost},
+    sort_comments::{CommentSelector, ThreadedComment},
 };
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Cursor, Read, Write},
+    path::PathBuf,
+    sync::OnceLock,
+};
+use uuid::Uuid;
 
 fn format_date(date: DateTime<Utc>) -> String {
     date.format("%Y-%m-%d").to_string()
@@ -22,82 +40,424 @@ fn words_to_read_time(words: i64) -> String {
     format!("{}min", minutes_round_up)
 }
 
+/// Metadata of the book
+#[derive(Debug, Clone)]
+pub struct BookMetadata {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub publisher: Option<String>,
+    pub description: Option<String>,
+    /// subject tags, e.g. "Rationality"
+    pub subjects: Vec<String>,
+    /// PNG, JPEG or GIF image. A cover with the title, authors and date is generated if `None`
+    pub cover: Option<PathBuf>,
+    /// printed on a generated cover
+    pub date: DateTime<Utc>,
+    /// `dc:identifier` of the book, see `book_identifier`
+    pub identifier: Uuid,
+}
+
+/// A UUID derived from the book's post ids in reading order, so rebuilds of the same book keep their identifier
+/// and e-readers recognise them as the same book
+pub fn book_identifier<S: AsRef<str>>(post_ids: &[S]) -> Uuid {
+    let post_ids = post_ids.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+    Uuid::new_v5(&Uuid::NAMESPACE_URL, post_ids.join(",").as_bytes())
+}
+
 pub struct Epub {
     builder: epub_builder::EpubBuilder<ZipLibrary>,
     image_embedder: ImageEmbedder,
+    /// maximum number of images downloaded concurrently
+    jobs: usize,
+    section_count: usize,
+    /// level of the innermost open section, posts are nested one level below it. 0 if no section was started
+    section_level: i32,
+    /// terms defined by the posts so far, for the glossary appendix
+    glossary: Vec<GlossaryEntry>,
+    /// review questions of the posts so far, for the answers appendix
+    answers: Vec<ChapterAnswers>,
+    /// language and labels of the templates
+    locale: Locale,
+    /// selects the comment threads rendered after each chapter, none if `None`
+    comment_selector: Option<CommentSelector>,
+    /// file names of the images added so far
+    image_files: HashSet<String>,
+    /// file names of the chapters containing MathML, declared with the `mathml` property EPUB 3 requires
+    mathml_files: HashSet<String>,
+    /// href inside the book of every post that is part of it, links to these posts are rewritten to stay in the book
+    post_links: HashMap<String, String>,
+    /// posts linked by the book's posts, for the referenced posts appendix
+    referenced_posts: Vec<ReferencedPost>,
+    /// chapters linking to each referenced post
+    citations: HashMap<String, Vec<Citation>>,
+    math_rendering: MathRendering,
+    /// renders formulas as images, see `render_math`
+    math_image_service: Option<String>,
+}
+
+/// A chapter linking to a referenced post
+#[derive(Debug, Clone, Serialize)]
+struct Citation {
+    chapter: String,
+    href: String,
+}
+
+const REFERENCED_POSTS_FILE: &str = "referenced-posts.xhtml";
+
+/// Anchor of a referenced post inside the referenced posts appendix
+fn referenced_post_id(post: &Post) -> String {
+    format!("ref-{}", post.slug)
+}
+
+// replies beyond this depth are indented like this depth, the pages are too narrow for more
+const MAX_COMMENT_INDENT: usize = 5;
+
+/// The answers to the review questions of a chapter
+#[derive(Debug, Serialize)]
+struct ChapterAnswers {
+    chapter: String,
+    answers: Vec<Answer>,
+}
+
+#[derive(Debug, Serialize)]
+struct Answer {
+    id: String,
+    question: String,
+    answer: String,
+    /// link back to the question in the chapter
+    href: String,
+}
+
+/// A glossary term with the chapter that introduced it
+#[derive(Debug, Serialize)]
+struct GlossaryEntry {
+    term: String,
+    definition: String,
+    /// link to the term's definition in the chapter
+    href: String,
+    chapter: String,
+}
+
+/// Anchor of a glossary term inside its chapter
+fn term_id(term: &str) -> String {
+    let slug = term
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    format!("term-{}", slug)
 }
 
 impl Default for Epub {
     fn default() -> Self {
+        Self::new(ImageEmbedder::default())
+    }
+}
+
+impl Epub {
+    pub fn new(image_embedder: ImageEmbedder) -> Self {
         let builder =
             epub_builder::EpubBuilder::new(epub_builder::ZipLibrary::new().unwrap()).unwrap();
 
         Self {
             builder,
-            image_embedder: ImageEmbedder::default(),
+            image_embedder,
+            jobs: 4,
+            section_count: 0,
+            section_level: 0,
+            glossary: vec![],
+            answers: vec![],
+            locale: Locale::default(),
+            comment_selector: None,
+            image_files: HashSet::new(),
+            mathml_files: HashSet::new(),
+            post_links: HashMap::new(),
+            referenced_posts: vec![],
+            citations: HashMap::new(),
+            math_rendering: MathRendering::default(),
+            math_image_service: None,
         }
     }
-}
 
-impl Epub {
-    pub fn set_metadata(
-        &mut self,
-        title: Option<String>,
-        author: Option<String>,
-        use_cover_image: bool,
-    ) -> Result<&mut Self> {
-        let stylesheet = std::fs::read("epub_resources/stylesheet.css")?;
+    pub fn set_jobs(&mut self, jobs: usize) -> &mut Self {
+        self.jobs = jobs.max(1);
+        self
+    }
 
-        // Default title if none provided
-        let title = title.unwrap_or_else(|| "LessWrong Sequences Highlights".to_string());
-        let author = author.unwrap_or_else(|| "Eliezer Yudkowsky".to_string());
+    /// Renders the comment threads the selector picks after every chapter
+    pub fn set_comment_selector(&mut self, comment_selector: Option<CommentSelector>) -> &mut Self {
+        self.comment_selector = comment_selector;
+        self
+    }
+
+    /// The posts that will be added as chapters. Links between them are rewritten to point to the chapters
+    pub fn set_book_posts<'a>(&mut self, posts: impl IntoIterator<Item = &'a Post>) -> &mut Self {
+        for post in posts {
+            self.post_links
+                .insert(post.id.clone(), format!("{}.xhtml", post.slug));
+        }
+        self
+    }
+
+    /// Posts the chapters link to that get a summary in the appendix added by `add_referenced_posts`.
+    /// Links to them are rewritten to their summary
+    pub fn set_referenced_posts(&mut self, posts: Vec<ReferencedPost>) -> &mut Self {
+        for referenced in &posts {
+            self.post_links.insert(
+                referenced.post.id.clone(),
+                format!(
+                    "{}#{}",
+                    REFERENCED_POSTS_FILE,
+                    referenced_post_id(&referenced.post)
+                ),
+            );
+        }
+        self.referenced_posts = posts;
+        self
+    }
+
+    pub fn set_math_rendering(&mut self, math_rendering: MathRendering) -> &mut Self {
+        self.math_rendering = math_rendering;
+        self
+    }
+
+    /// Service rendering the formulas that can't be converted to MathML to images, e.g. `https://latex.codecogs.com/png.image`.
+    /// Without it they are rendered locally as their LaTeX source
+    pub fn set_math_image_service(&mut self, image_service: Option<String>) -> &mut Self {
+        self.math_image_service = image_service;
+        self
+    }
+
+    /// Language of the book, set as `dc:language` by `set_metadata` and used for the labels of the templates
+    pub fn set_locale(&mut self, locale: Locale) -> &mut Self {
+        self.locale = locale;
+        self
+    }
+
+    /// Sets the book's metadata, stylesheet and cover. Call it before adding any content,
+    /// the title page for the publisher has to come first
+    pub fn set_metadata(&mut self, metadata: &BookMetadata) -> Result<&mut Self> {
+        let stylesheet = std::fs::read("epub_resources/stylesheet.css")?;
 
-        // Kindle shows <bold>filename</bold><br/><small>author</small>. if no cover image
         self.builder
             .stylesheet(stylesheet.as_slice())
             .map_err(|e| anyhow::anyhow!(e))?
-            .metadata("author", &author)
+            .metadata("title", &metadata.title)
             .map_err(|e| anyhow::anyhow!(e))?
-            .metadata("title", &title)
+            .metadata("lang", &self.locale.language)
             .map_err(|e| anyhow::anyhow!(e))?
             .epub_version(epub_builder::EpubVersion::V30)
-            .set_title(&title);
+            .set_title(&metadata.title)
+            .set_uuid(metadata.identifier);
+        for author in &metadata.authors {
+            self.builder
+                .metadata("author", author)
+                .map_err(|e| anyhow::anyhow!(e))?;
+        }
+        if let Some(description) = &metadata.description {
+            self.builder
+                .metadata("description", description)
+                .map_err(|e| anyhow::anyhow!(e))?;
+        }
+        for subject in &metadata.subjects {
+            self.builder
+                .metadata("subject", subject)
+                .map_err(|e| anyhow::anyhow!(e))?;
+        }
+
+        // Kindle shows <bold>filename</bold><br/><small>author</small>. if no cover image
+        let (cover_image, format) = match &metadata.cover {
+            Some(cover_path) => {
+                let cover_image = std::fs::read(cover_path)
+                    .with_context(|| format!("Failed to read cover image {:?}", cover_path))?;
+                let format = ImageFormat::sniff(&cover_image).ok_or_else(|| {
+                    anyhow::anyhow!("Cover image {:?} is no PNG, JPEG or GIF", cover_path)
+                })?;
+                (cover_image, format)
+            }
+            None => (
+                generate_cover(&metadata.title, &metadata.authors, metadata.date)?,
+                ImageFormat::Png,
+            ),
+        };
+        self.builder
+            .add_cover_image(
+                format!("cover.{}", format.extension()),
+                cover_image.as_slice(),
+                format.mime_type(),
+            )
+            .map_err(|e| anyhow::anyhow!(e))?;
 
-        // Use the provided cover image or fall back to the default one
-        if use_cover_image {
-            let cover_path = "epub_resources/cover.jpg".to_string();
-            let cover_image = std::fs::read(&cover_path)?;
+        // epub-builder has no dc:publisher, the publisher is shown on a title page instead
+        if let Some(publisher) = &metadata.publisher {
+            let template = std::fs::read_to_string("epub_resources/title_page.html.hbs")?;
+            let reg = Handlebars::new();
+            let xhtml = reg.render_template(
+                &template,
+                &json!({ "title": metadata.title, "authors": metadata.authors.join(", "), "publisher": publisher, "description": metadata.description, "lang": self.locale.language }),
+            )?;
             self.builder
-                .add_cover_image("cover.jpg", cover_image.as_ref() as &[u8], "image/jpeg")
+                .add_content(
+                    EpubContent::new("title-page.xhtml", xhtml.as_bytes())
+                        .title(metadata.title.clone())
+                        .reftype(ReferenceType::TitlePage),
+                )
                 .map_err(|e| anyhow::anyhow!(e))?;
         }
 
         Ok(self)
     }
 
+    /// Starts a new section of the book, e.g. a book (level 1) or a sequence (level 2) of a collection.
+    /// Writes a title page for it and nests all following posts below it in the table of contents,
+    /// until a section of the same or a higher level is started. The optional markdown `intro` is shown below the title.
+    pub fn begin_section(
+        &mut self,
+        title: &str,
+        level: i32,
+        intro: Option<&str>,
+    ) -> Result<&mut Self> {
+        if level < 1 || level > self.section_level + 1 {
+            return Err(anyhow::anyhow!(
+                "Cannot begin a level {} section \"{}\" inside a level {} section",
+                level,
+                title,
+                self.section_level
+            ));
+        }
+        self.section_count += 1;
+        self.section_level = level;
+
+        let template = std::fs::read_to_string("epub_resources/section.html.hbs")?;
+        let reg = Handlebars::new();
+        let xhtml = reg.render_template(
+            &template,
+            &json!({ "title": title, "level": level, "intro": intro.map(markdown::to_html), "lang": self.locale.language }),
+        )?;
+
+        self.builder
+            .add_content(
+                EpubContent::new(
+                    format!("section-{}.xhtml", self.section_count),
+                    xhtml.as_bytes(),
+                )
+                .title(title)
+                .level(level)
+                .reftype(ReferenceType::Text),
+            )
+            .map_err(|e| anyhow::anyhow!(e))?;
+        Ok(self)
+    }
+
     pub async fn add_post(&mut self, post: &AnnotatedPostWithComments) -> Result<&mut Self> {
         // convert the markdown to html instead of using content_html because the HTML output is cleaner this way. epub html also errors on some tags that are not closed like <hr>
-        let post_html = markdown::to_html(&post.post.content_markdown);
+        let post_html = markdown_to_html(&post.post.content_markdown);
+        let post_html = link_footnotes(&post_html);
+        // links to the post's sections from other chapters point to these ids
+        let post_html = add_heading_ids(&post_html);
+        // before the images are embedded, so formulas rendered as images are embedded with them
+        let post_html = render_math(
+            &post_html,
+            self.math_rendering,
+            self.math_image_service.as_deref(),
+        );
         let (post_html, replacements) = self.try_inline_images(&post.post, post_html).await?;
+        let file_name = format!("{}.xhtml", post.post.slug);
+        for post_id in linked_post_ids(&post_html) {
+            if self
+                .referenced_posts
+                .iter()
+                .any(|referenced| referenced.post.id == post_id)
+            {
+                self.citations.entry(post_id).or_default().push(Citation {
+                    chapter: post.post.title.clone(),
+                    href: file_name.clone(),
+                });
+            }
+        }
+        let post_html = rewrite_post_links(&post_html, &self.post_links);
 
         let post_summary_html = markdown::to_html(&post.post_summary);
         let comments_summary_html = markdown::to_html(&post.comments_summary);
 
+        // the glossary links to the chapter that introduced a term first
+        let mut terms = vec![];
+        for term in &post.key_takeaways.glossary {
+            let id = term_id(&term.term);
+            if terms.iter().any(|t: &serde_json::Value| t["id"] == id) {
+                continue;
+            }
+            terms.push(json!({ "id": id, "term": term.term, "definition": term.definition }));
+            if !self
+                .glossary
+                .iter()
+                .any(|e| e.href.ends_with(&format!("#{}", id)))
+            {
+                self.glossary.push(GlossaryEntry {
+                    term: term.term.clone(),
+                    definition: term.definition.clone(),
+                    href: format!("{}#{}", file_name, id),
+                    chapter: post.post.title.clone(),
+                });
+            }
+        }
+
+        // questions link to their answers in the appendix and back
+        let questions = post
+            .review_questions
+            .iter()
+            .enumerate()
+            .map(|(i, q)| {
+                json!({ "id": format!("question-{}", i + 1), "question": q.question, "href": format!("answers.xhtml#answer-{}-{}", post.post.slug, i + 1) })
+            })
+            .collect::<Vec<_>>();
+        if !post.review_questions.is_empty() {
+            self.answers.push(ChapterAnswers {
+                chapter: post.post.title.clone(),
+                answers: post
+                    .review_questions
+                    .iter()
+                    .enumerate()
+                    .map(|(i, q)| Answer {
+                        id: format!("answer-{}-{}", post.post.slug, i + 1),
+                        question: q.question.clone(),
+                        answer: q.answer.clone(),
+                        href: format!("{}#question-{}", file_name, i + 1),
+                    })
+                    .collect(),
+            });
+        }
+
         let template = std::fs::read_to_string("epub_resources/post.html.hbs")?;
         let reg = Handlebars::new();
         let xhtml = reg.render_template(
             &template,
-            &json!({"title": post.post.title, "body": post_html, "date": format_date(post.post.date), "author": post.post.author, "read_time": words_to_read_time(post.post.word_count), "post_summary": post_summary_html, "comments_summary": comments_summary_html }),
+            &json!({"title": post.post.title, "body": post_html, "date": format_date(post.post.date), "author": post.post.author, "read_time": words_to_read_time(post.post.word_count), "post_summary": post_summary_html, "comments_summary": comments_summary_html, "takeaways": post.key_takeaways.takeaways, "terms": terms, "questions": questions, "lang": self.locale.language, "labels": self.locale.labels() }),
         )?;
 
+        if xhtml.contains("<math") {
+            self.mathml_files.insert(file_name.clone());
+        }
         self.builder
             .add_content(
-                EpubContent::new(format!("{}.xhtml", post.post.slug), xhtml.as_bytes())
+                EpubContent::new(file_name, xhtml.as_bytes())
                     .title(post.post.title.clone())
+                    .level(self.section_level + 1)
                     .reftype(ReferenceType::Text),
             )
             .map_err(|e| anyhow::anyhow!(e))?;
+        self.add_images(replacements)?;
 
+        if let Some(comment_selector) = &self.comment_selector {
+            let threads = comment_selector.select_threads(&post.comments, &post.post.author);
+            self.add_top_comments(post, threads).await?;
+        }
+        Ok(self)
+    }
+
+    /// Adds the embedded images as resources, each file only once even if several chapters use it
+    fn add_images(&mut self, replacements: Vec<EmbeddingResult>) -> Result<()> {
         replacements
             .into_iter()
             .filter_map(|r| {
@@ -107,18 +467,142 @@ impl Epub {
                     None
                 }
             })
+            .filter(|embedded_image| self.image_files.insert(embedded_image.file_name()))
             .try_for_each(|embedded_image| {
                 self.builder
                     .add_resource(
-                        // apparently Cloudflare's API returns all images in png format, even though their example says webp
-                        format!("{}.png", embedded_image.id),
+                        embedded_image.file_name(),
                         embedded_image.image_bytes.as_slice(),
-                        "image/png",
+                        embedded_image.format.mime_type(),
                     )
                     // Convert success value to () to match try_for_each's expected return type
                     .map(|_| ())
                     .map_err(|e| anyhow::anyhow!(e))
-            })?;
+            })
+    }
+
+    /// Adds the top comment threads of the post as an appendix to its chapter
+    async fn add_top_comments(
+        &mut self,
+        post: &AnnotatedPostWithComments,
+        threads: Vec<Vec<ThreadedComment>>,
+    ) -> Result<()> {
+        if threads.is_empty() {
+            return Ok(());
+        }
+
+        let mut replacements = vec![];
+        let mut rendered_threads = vec![];
+        for thread in threads {
+            let mut rendered_thread = vec![];
+            for ThreadedComment { comment, depth } in thread {
+                let html = markdown::to_html(&comment.content_markdown);
+                let (html, comment_replacements) = self.try_inline_images(&post.post, html).await?;
+                replacements.extend(comment_replacements);
+                rendered_thread.push(json!({
+                    "depth": depth.min(MAX_COMMENT_INDENT),
+                    "author": comment.author,
+                    "karma": format!("{:.0}", comment.base_score),
+                    "date": format_date(comment.date),
+                    "body": html,
+                }));
+            }
+            rendered_threads.push(rendered_thread);
+        }
+
+        let title = format!("{}: {}", self.locale.label("top_comments"), post.post.title);
+        let template = std::fs::read_to_string("epub_resources/comments.html.hbs")?;
+        let reg = Handlebars::new();
+        let xhtml = reg.render_template(
+            &template,
+            &json!({ "title": title, "threads": rendered_threads, "lang": self.locale.language, "labels": self.locale.labels() }),
+        )?;
+
+        self.builder
+            .add_content(
+                EpubContent::new(
+                    format!("{}-comments.xhtml", post.post.slug),
+                    xhtml.as_bytes(),
+                )
+                .title(self.locale.label("top_comments"))
+                .level(self.section_level + 2)
+                .reftype(ReferenceType::Text),
+            )
+            .map_err(|e| anyhow::anyhow!(e))?;
+        self.add_images(replacements)
+    }
+
+    /// Adds an appendix listing the terms the posts defined, each linking to the chapter that introduced it.
+    /// Does nothing if no post has glossary terms
+    pub fn add_glossary(&mut self) -> Result<&mut Self> {
+        if self.glossary.is_empty() {
+            return Ok(self);
+        }
+        self.glossary.sort_by_key(|entry| entry.term.to_lowercase());
+
+        let template = std::fs::read_to_string("epub_resources/glossary.html.hbs")?;
+        let reg = Handlebars::new();
+        let xhtml = reg.render_template(&template, &json!({ "terms": self.glossary, "lang": self.locale.language, "labels": self.locale.labels() }),)?;
+
+        self.builder
+            .add_content(
+                EpubContent::new("glossary.xhtml", xhtml.as_bytes())
+                    .title(self.locale.label("glossary"))
+                    .reftype(ReferenceType::Glossary),
+            )
+            .map_err(|e| anyhow::anyhow!(e))?;
+        Ok(self)
+    }
+
+    /// Adds an appendix with the answers to the review questions, grouped by chapter.
+    /// Does nothing if no post has review questions
+    pub fn add_answers(&mut self) -> Result<&mut Self> {
+        if self.answers.is_empty() {
+            return Ok(self);
+        }
+
+        let template = std::fs::read_to_string("epub_resources/answers.html.hbs")?;
+        let reg = Handlebars::new();
+        let xhtml = reg.render_template(&template, &json!({ "chapters": self.answers, "lang": self.locale.language, "labels": self.locale.labels() }),)?;
+
+        self.builder
+            .add_content(
+                EpubContent::new("answers.xhtml", xhtml.as_bytes())
+                    .title(self.locale.label("answers"))
+                    .reftype(ReferenceType::Notes),
+            )
+            .map_err(|e| anyhow::anyhow!(e))?;
+        Ok(self)
+    }
+
+    /// Adds an appendix with the summaries of the referenced posts, each listing the chapters linking to it.
+    /// Does nothing if there are no referenced posts
+    pub fn add_referenced_posts(&mut self) -> Result<&mut Self> {
+        if self.referenced_posts.is_empty() {
+            return Ok(self);
+        }
+
+        let posts = self
+            .referenced_posts
+            .iter()
+            .map(|referenced| {
+                let post = &referenced.post;
+                let summary_html =
+                    rewrite_post_links(&markdown::to_html(&referenced.summary), &self.post_links);
+                json!({ "id": referenced_post_id(post), "title": post.title, "author": post.author, "date": format_date(post.date), "url": post.page_url, "summary": summary_html, "cited_by": self.citations.get(&post.id).cloned().unwrap_or_default() })
+            })
+            .collect::<Vec<_>>();
+        let template = std::fs::read_to_string("epub_resources/referenced_posts.html.hbs")?;
+        let reg = Handlebars::new();
+        let xhtml = reg.render_template(&template, &json!({ "posts": posts, "lang": self.locale.language, "labels": self.locale.labels() }),)?;
+
+        self.builder
+            .add_content(
+                EpubContent::new(REFERENCED_POSTS_FILE, xhtml.as_bytes())
+                    .title(self.locale.label("referenced_posts"))
+                    .reftype(ReferenceType::Bibliography),
+            )
+            .map_err(|e| anyhow::anyhow!(e))?;
         Ok(self)
     }
 
@@ -128,7 +612,13 @@ impl Epub {
         self.builder
             .generate(&mut output)
             .map_err(|e| anyhow::anyhow!(e))?;
-        Ok(output)
+        if self.mathml_files.is_empty() {
+            return Ok(output);
+        }
+        // epub-builder can't set the properties of manifest items
+        rewrite_package_document(&output, |opf| {
+            add_item_properties(opf, &self.mathml_files, "mathml")
+        })
     }
 
     async fn try_inline_images(
@@ -136,7 +626,8 @@ impl Epub {
         post: &Post,
         html: String,
     ) -> Result<(String, Vec<EmbeddingResult>)> {
-        let mut replaced: Vec<EmbeddingResult> = vec![];
+        // collect the images first, the rewriter's handlers can't wait for the downloads
+        let mut images: Vec<(String, Option<String>)> = vec![];
         let element_content_handlers = vec![element!("img[src]", |el| {
             let img_src = el.get_attribute("src").unwrap().trim().to_string();
             let img_alt = el
@@ -149,22 +640,65 @@ impl Epub {
             } else {
                 Some(img_alt)
             };
-            let embedding = self.image_embedder.embed_image(post, &img_src, &img_alt)?;
+            images.push((img_src, img_alt));
+            Ok(())
+        })];
+        rewrite_str(
+            html.as_str(),
+            RewriteStrSettings {
+                element_content_handlers,
+                ..RewriteStrSettings::new()
+            },
+        )
+        .unwrap();
+
+        let mut replaced = images
+            .iter()
+            .map(|(img_src, _)| self.image_embedder.embed_image(post, img_src))
+            .collect::<Result<Vec<_>>>()?;
 
-            match &embedding {
-                EmbeddingResult::Text(replaced_html) => {
+        // images that fail to download are replaced by a link instead of failing the whole book
+        stream::iter(replaced.iter_mut().zip(images.iter()))
+            .for_each_concurrent(self.jobs, |(result, (img_src, img_alt))| async move {
+                let EmbeddingResult::Image(embedding) = result else {
+                    return;
+                };
+                if let Err(e) = self.image_embedder.download_image(embedding).await {
+                    println!("Failed to embed image {}: {:#}", embedding.old_url, e);
+                    *result = self
+                        .image_embedder
+                        .link_image(&embedding.old_url, img_src, img_alt);
+                }
+            })
+            .await;
+
+        let mut replacements = replaced.iter();
+        let element_content_handlers = vec![element!("img[src]", |el| {
+            match replacements.next() {
+                Some(EmbeddingResult::Text(replaced_html)) => {
                     el.after(replaced_html, ContentType::Html);
                 }
-                EmbeddingResult::Image(embedded_image) => {
+                Some(EmbeddingResult::Image(embedded_image)) => {
+                    // e.g. the size and baseline of rendered formulas
+                    let attributes = ["class", "alt", "style"]
+                        .into_iter()
+                        .filter_map(|name| {
+                            let value = el.get_attribute(name)?;
+                            Some(format!(" {}=\"{}\"", name, value.replace('"', "&quot;")))
+                        })
+                        .collect::<String>();
                     el.after(
-                        &format!("<img src=\"{}.png\" />", embedded_image.id),
+                        &format!(
+                            "<img src=\"{}\"{} />",
+                            embedded_image.file_name(),
+                            attributes
+                        ),
                         ContentType::Html,
                     );
                 }
+                None => {}
             }
             el.remove();
-
-            replaced.push(embedding);
             Ok(())
         })];
         let output = rewrite_str(
@@ -176,27 +710,109 @@ impl Epub {
         )
         .unwrap();
 
-        for result in &mut replaced.iter_mut() {
-            match result {
-                EmbeddingResult::Image(embedding) => {
-                    self.image_embedder.download_image(embedding).await?;
-                }
-                EmbeddingResult::Text(_) => {}
+        Ok((output, replaced))
+    }
+}
+
+/// Adds the `property` to the manifest items of the package document whose file name is in `files`
+fn add_item_properties(opf: &str, files: &HashSet<String>, property: &str) -> String {
+    static ITEM_RE: OnceLock<Regex> = OnceLock::new();
+    static HREF_RE: OnceLock<Regex> = OnceLock::new();
+    static PROPERTIES_RE: OnceLock<Regex> = OnceLock::new();
+    let item_re = ITEM_RE.get_or_init(|| Regex::new(r"<item\b[^>]*>").unwrap());
+    let href_re = HREF_RE.get_or_init(|| Regex::new(r#"\bhref="([^"]*)""#).unwrap());
+    let properties_re =
+        PROPERTIES_RE.get_or_init(|| Regex::new(r#"\bproperties="([^"]*)""#).unwrap());
+    item_re
+        .replace_all(opf, |captures: &Captures| {
+            let item = &captures[0];
+            let matches = href_re
+                .captures(item)
+                .is_some_and(|href| files.contains(href[1].rsplit('/').next().unwrap_or_default()));
+            if !matches {
+                return item.to_string();
             }
-        }
+            match properties_re.captures(item) {
+                Some(properties) => item.replacen(
+                    &properties[0],
+                    &format!("properties=\"{} {}\"", &properties[1], property),
+                    1,
+                ),
+                None => item.replacen("<item", &format!("<item properties=\"{}\"", property), 1),
+            }
+        })
+        .into_owned()
+}
 
-        Ok((output, replaced))
+/// Rewrites the package document (`.opf`) of an EPUB, all other files are copied unchanged
+fn rewrite_package_document(epub: &[u8], rewrite: impl Fn(&str) -> String) -> Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(Cursor::new(epub))?;
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    for i in 0..archive.len() {
+        let name = archive.by_index_raw(i)?.name().to_string();
+        if !name.ends_with(".opf") {
+            // keeps the mimetype file first and uncompressed
+            writer.raw_copy_file(archive.by_index_raw(i)?)?;
+            continue;
+        }
+        let mut opf = String::new();
+        archive.by_index(i)?.read_to_string(&mut opf)?;
+        writer.start_file(
+            name,
+            FileOptions::default().compression_method(CompressionMethod::Deflated),
+        )?;
+        writer.write_all(rewrite(&opf).as_bytes())?;
     }
+    Ok(writer.finish()?.into_inner())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        ai::{GlossaryTerm, KeyTakeaways},
+        cache::Cache,
+        image_renderer::{
+            DirectDownloadRenderer, ImageFormat, ImageRenderer, RenderedImage, SourceImage,
+        },
+    };
+    use async_trait::async_trait;
     use lesswrong_api::Post;
+    use std::collections::HashMap;
+
+    /// An embedder caching into a temporary directory, so tests don't touch the developer's `.cache/images`
+    fn test_embedder(test: &str, renderers: Vec<Box<dyn ImageRenderer>>) -> ImageEmbedder {
+        let directory =
+            std::env::temp_dir().join(format!("epub-test-images-{}-{}", test, std::process::id()));
+        // leftovers of an earlier run would make the renderers irrelevant
+        let _ = std::fs::remove_dir_all(&directory);
+        ImageEmbedder::with_cache(renderers, Cache::in_directory(directory))
+    }
+
+    /// Renders `.gif` URLs as a tiny GIF without touching the network, fails for everything else
+    struct FakeRenderer;
+
+    #[async_trait]
+    impl ImageRenderer for FakeRenderer {
+        fn name(&self) -> &'static str {
+            "fake"
+        }
+
+        async fn render(&self, image: &SourceImage) -> Result<RenderedImage> {
+            if !image.url().ends_with(".gif") {
+                return Err(anyhow::anyhow!("not a gif"));
+            }
+            Ok(RenderedImage {
+                bytes: b"GIF89a\x01\x00\x01\x00".to_vec(),
+                format: ImageFormat::Gif,
+            })
+        }
+    }
 
     #[tokio::test]
     async fn replaces_multiple_images_as_text() -> Result<()> {
-        let epub = Epub::default();
+        // no renderers, so every image falls back to a link
+        let epub = Epub::new(test_embedder("text", vec![]));
 
         let post = Post {
             id: "test-epub".to_string(),
@@ -225,4 +841,165 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn embeds_rendered_images_with_their_format() -> Result<()> {
+        let epub = Epub::new(test_embedder("fake-renderer", vec![Box::new(FakeRenderer)]));
+
+        let post = Post {
+            id: "test-epub-fake-renderer".to_string(),
+            page_url: "https://example.com/test-post".to_string(),
+            ..Post::default()
+        };
+
+        let input = r#"<p><img src="animation.gif"><img src="diagram.svg" alt="Diagram"></p>"#;
+
+        let (output, replaced) = epub.try_inline_images(&post, input.to_string()).await?;
+
+        let EmbeddingResult::Image(embedded_image) = &replaced[0] else {
+            panic!("gif was not embedded");
+        };
+        assert_eq!(embedded_image.format, ImageFormat::Gif);
+        assert!(embedded_image.file_name().ends_with(".gif"));
+        assert!(output.contains(&format!(r#"<img src="{}" />"#, embedded_image.file_name())));
+        assert!(output.contains(
+            r#"<a href="https://example.com/diagram.svg">Unsupported SVG image: Diagram</a>"#
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn embeds_formulas_rendered_as_images() -> Result<()> {
+        let epub = Epub::new(test_embedder(
+            "formulas",
+            vec![Box::new(DirectDownloadRenderer)],
+        ));
+
+        let post = Post {
+            id: "test-epub-formulas".to_string(),
+            page_url: "https://example.com/test-post".to_string(),
+            ..Post::default()
+        };
+
+        let html = render_math(
+            &markdown_to_html("Energy is $E = mc^2$."),
+            MathRendering::Image,
+            None,
+        );
+        let (output, replaced) = epub.try_inline_images(&post, html).await?;
+
+        let EmbeddingResult::Image(embedded_image) = &replaced[0] else {
+            panic!("formula was not embedded");
+        };
+        assert_eq!(embedded_image.format, ImageFormat::Png);
+        assert!(output.contains(&format!(
+            r#"<img src="{}" class="math-inline" alt="E = mc^2" style="height: "#,
+            embedded_image.file_name()
+        )));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn collects_glossary_terms_from_the_first_chapter() -> Result<()> {
+        let mut epub = Epub::new(test_embedder("glossary", vec![]));
+        let term = |term: &str| GlossaryTerm {
+            term: term.to_string(),
+            definition: format!("Definition of {}", term),
+        };
+        for (slug, terms) in [
+            (
+                "first",
+                vec![term("Map and territory"), term("Floating belief")],
+            ),
+            ("second", vec![term("map and territory")]),
+        ] {
+            let post = AnnotatedPostWithComments {
+                post: Post {
+                    slug: slug.to_string(),
+                    title: slug.to_string(),
+                    ..Post::default()
+                },
+                comments: HashMap::new(),
+                post_summary: String::new(),
+                comments_summary: String::new(),
+                key_takeaways: KeyTakeaways {
+                    takeaways: vec!["A takeaway".to_string()],
+                    glossary: terms,
+                },
+                review_questions: vec![],
+            };
+            epub.add_post(&post).await?;
+        }
+        epub.add_glossary()?;
+
+        let hrefs = epub
+            .glossary
+            .iter()
+            .map(|e| e.href.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            hrefs,
+            vec![
+                "first.xhtml#term-floating-belief",
+                "first.xhtml#term-map-and-territory"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn nests_sections_one_level_at_a_time() -> Result<()> {
+        let mut epub = Epub::default();
+
+        assert!(epub.begin_section("Sequence", 2, None).is_err());
+        epub.begin_section("Book", 1, Some("An *introduction*."))?;
+        epub.begin_section("Sequence", 2, None)?;
+        assert!(epub.begin_section("Too deep", 4, None).is_err());
+        epub.begin_section("Next book", 1, None)?;
+        assert_eq!(epub.section_level, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn derives_stable_identifier_from_post_ids() {
+        let identifier = book_identifier(&["a", "b"]);
+
+        assert_eq!(
+            identifier,
+            book_identifier(&["a".to_string(), "b".to_string()])
+        );
+        assert_ne!(identifier, book_identifier(&["b", "a"]));
+        assert_eq!(identifier.get_version_num(), 5);
+    }
+
+    #[test]
+    fn declares_mathml_chapters_in_the_package_document() -> Result<()> {
+        let opf = r#"<manifest><item id="nav" href="nav.xhtml" properties="nav"/><item id="a" href="a.xhtml" media-type="application/xhtml+xml"/><item id="b" href="b.xhtml" media-type="application/xhtml+xml"/></manifest>"#;
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+        writer.start_file("mimetype", stored)?;
+        writer.write_all(b"application/epub+zip")?;
+        writer.start_file("OEBPS/content.opf", stored)?;
+        writer.write_all(opf.as_bytes())?;
+        let epub = writer.finish()?.into_inner();
+
+        let files = HashSet::from(["a.xhtml".to_string(), "nav.xhtml".to_string()]);
+        let epub =
+            rewrite_package_document(&epub, |opf| add_item_properties(opf, &files, "mathml"))?;
+
+        let mut archive = ZipArchive::new(Cursor::new(epub))?;
+        assert_eq!(archive.by_index(0)?.name(), "mimetype");
+        let mut opf = String::new();
+        archive
+            .by_name("OEBPS/content.opf")?
+            .read_to_string(&mut opf)?;
+        assert_eq!(
+            opf,
+            r#"<manifest><item id="nav" href="nav.xhtml" properties="nav mathml"/><item properties="mathml" id="a" href="a.xhtml" media-type="application/xhtml+xml"/><item id="b" href="b.xhtml" media-type="application/xhtml+xml"/></manifest>"#
+        );
+        Ok(())
+    }
 }
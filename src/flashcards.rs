@@ -0,0 +1,55 @@
+use crate::ai::AnnotatedPostWithComments;
+
+/// Anki only allows a single line per note in text imports
+fn anki_field(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Review questions of the posts as an Anki-importable TSV file, one note per question tagged with the post's slug.
+/// The header lines tell Anki how to import it (File > Import), see https://docs.ankiweb.net/importing/text-files.html
+pub fn anki_tsv<'a>(posts: impl IntoIterator<Item = &'a AnnotatedPostWithComments>) -> String {
+    let mut tsv = String::from("#separator:tab\n#html:false\n#tags column:3\n");
+    for post in posts {
+        for question in &post.review_questions {
+            tsv.push_str(&format!(
+                "{}\t{}\t{}\n",
+                anki_field(&question.question),
+                anki_field(&question.answer),
+                anki_field(&post.post.slug)
+            ));
+        }
+    }
+    tsv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::{KeyTakeaways, ReviewQuestion};
+    use lesswrong_api::Post;
+    use std::collections::HashMap;
+
+    #[test]
+    fn exports_one_line_per_question() {
+        let post = AnnotatedPostWithComments {
+            post: Post {
+                slug: "making-beliefs-pay-rent".to_string(),
+                ..Post::default()
+            },
+            comments: HashMap::new(),
+            post_summary: String::new(),
+            comments_summary: String::new(),
+            key_takeaways: KeyTakeaways::default(),
+            review_questions: vec![ReviewQuestion {
+                question: "What is a\tfloating belief?".to_string(),
+                answer: "A belief that\nconstrains no experience.".to_string(),
+            }],
+        };
+
+        assert_eq!(
+            anki_tsv([&post]),
+            "#separator:tab\n#html:false\n#tags column:3\n\
+             What is a floating belief?\tA belief that constrains no experience.\tmaking-beliefs-pay-rent\n"
+        );
+    }
+}
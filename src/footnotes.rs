@@ -0,0 +1,170 @@
+use regex::{Captures, Regex};
+use std::sync::OnceLock;
+
+// the back-link in front of each footnote's content. LessWrong renders it as `^`, sometimes bold and/or linked
+const BACK_LINK_MARKER: &str = r"(?:<strong>)?(?:<a [^>]*>)?\^(?:</a>)?(?:</strong>)?";
+
+struct Footnote {
+    number: usize,
+    content: String,
+}
+
+/// Turns the footnotes of a post's HTML (converted from LessWrong's markdown) into EPUB 3 noteref/footnote pairs.
+/// References are written as `^1^` (or `^[1]^` with a link), the footnotes themselves are the last ordered list
+/// whose items start with a `^` marker. If there is no such list, the HTML is returned unchanged.
+pub fn link_footnotes(html: &str) -> String {
+    static LIST_RE: OnceLock<Regex> = OnceLock::new();
+    let list_re = LIST_RE.get_or_init(|| {
+        Regex::new(&format!(
+            r#"(?s)<ol(?: start="(\d+)")?>\s*((?:<li>\s*(?:<p>)?{}.*?</li>\s*)+)</ol>"#,
+            BACK_LINK_MARKER
+        ))
+        .unwrap()
+    });
+    let Some(list) = list_re.captures_iter(html).last() else {
+        return html.to_string();
+    };
+
+    let start = list
+        .get(1)
+        .and_then(|m| m.as_str().parse::<usize>().ok())
+        .unwrap_or(1);
+    let footnotes = parse_footnotes(&list[2], start);
+    let list_range = list.get(0).unwrap().range();
+
+    // only the text before the footnotes references them, the footnotes and anything after are left as they are
+    format!(
+        "{}{}{}",
+        link_references(&html[..list_range.start], &footnotes),
+        render_footnotes(&footnotes),
+        &html[list_range.end..]
+    )
+}
+
+fn parse_footnotes(list_items: &str, start: usize) -> Vec<Footnote> {
+    static ITEM_RE: OnceLock<Regex> = OnceLock::new();
+    let item_re = ITEM_RE.get_or_init(|| {
+        Regex::new(&format!(
+            r"(?s)<li>\s*(<p>)?{}\s*(</p>)?\s*(.*?)</li>",
+            BACK_LINK_MARKER
+        ))
+        .unwrap()
+    });
+
+    item_re
+        .captures_iter(list_items)
+        .enumerate()
+        .map(|(index, item)| {
+            let content = item[3].trim();
+            // the marker can share its paragraph with the footnote text, re-open it in that case
+            let content = if item.get(1).is_some() && item.get(2).is_none() {
+                format!("<p>{}", content)
+            } else {
+                content.to_string()
+            };
+            Footnote {
+                number: start + index,
+                content,
+            }
+        })
+        .collect()
+}
+
+fn render_footnotes(footnotes: &[Footnote]) -> String {
+    let asides = footnotes
+        .iter()
+        .map(|footnote| {
+            let back_link = format!(
+                "<a class=\"footnote-back-link\" href=\"#fnref-{0}\">{0}.</a> ",
+                footnote.number
+            );
+            // put the back-link into the first paragraph so it doesn't end up on its own line
+            let content = match footnote.content.strip_prefix("<p>") {
+                Some(rest) => format!("<p>{}{}", back_link, rest),
+                None => format!("<p>{}</p>{}", back_link, footnote.content),
+            };
+            format!(
+                "<aside id=\"fn-{}\" class=\"footnote\" epub:type=\"footnote\">{}</aside>",
+                footnote.number, content
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!(
+        "<section class=\"footnotes\" epub:type=\"footnotes\">\n{}\n</section>",
+        asides
+    )
+}
+
+fn link_references(html: &str, footnotes: &[Footnote]) -> String {
+    static REFERENCE_RE: OnceLock<Regex> = OnceLock::new();
+    let reference_re =
+        REFERENCE_RE.get_or_init(|| Regex::new(r"\^(?:<a [^>]*>)?\[?(\d+)\]?(?:</a>)?\^").unwrap());
+    let mut linked = Vec::new();
+
+    reference_re
+        .replace_all(html, |caps: &Captures| {
+            let number = caps[1].parse::<usize>().unwrap_or_default();
+            if !footnotes.iter().any(|f| f.number == number) {
+                return caps[0].to_string();
+            }
+            // a footnote can be referenced several times, but the back-link can only target the first reference
+            let id = if linked.contains(&number) {
+                String::new()
+            } else {
+                linked.push(number);
+                format!(" id=\"fnref-{}\"", number)
+            };
+            format!(
+                "<sup><a{} class=\"footnote-ref\" href=\"#fn-{}\" epub:type=\"noteref\">{}</a></sup>",
+                id, number, number
+            )
+        })
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_references_to_footnotes() {
+        let markdown = "Some claim.^1^ Linked^[\\[2\\]](#fn-abc-2)^ and again^1^.\n\n1.  ^\n    \n    First *footnote*.\n    \n2.  **[^](#fnref-abc-2)**\n    \n    Second.\n";
+        let html = link_footnotes(&markdown::to_html(markdown));
+
+        assert!(html.contains(
+            r##"<sup><a id="fnref-1" class="footnote-ref" href="#fn-1" epub:type="noteref">1</a></sup>"##
+        ));
+        assert!(html.contains(
+            r##"<sup><a id="fnref-2" class="footnote-ref" href="#fn-2" epub:type="noteref">2</a></sup>"##
+        ));
+        // only the first reference gets the back-link target
+        assert!(html.contains(
+            r##"again<sup><a class="footnote-ref" href="#fn-1" epub:type="noteref">1</a></sup>"##
+        ));
+        assert!(html.contains(
+            r##"<aside id="fn-1" class="footnote" epub:type="footnote"><p><a class="footnote-back-link" href="#fnref-1">1.</a> First <em>footnote</em>.</p></aside>"##
+        ));
+        assert!(html.contains(
+            r##"<aside id="fn-2" class="footnote" epub:type="footnote"><p><a class="footnote-back-link" href="#fnref-2">2.</a> Second.</p></aside>"##
+        ));
+        assert!(!html.contains("<ol>"));
+    }
+
+    #[test]
+    fn links_only_references_before_the_footnotes() {
+        let markdown = "A claim.^1^\n\n1.  ^\n    \n    Note with 2^1^ in it.\n    \n\nAfter the notes, x^1^.\n";
+        let html = link_footnotes(&markdown::to_html(markdown));
+
+        assert_eq!(html.matches("epub:type=\"noteref\"").count(), 1);
+        assert!(html.contains("Note with 2^1^ in it."));
+        assert!(html.contains("After the notes, x^1^."));
+    }
+
+    #[test]
+    fn leaves_html_without_footnotes_unchanged() {
+        let html = markdown::to_html("2^10^ is a lot.\n\n1. first\n2. second\n");
+        assert_eq!(link_footnotes(&html), html);
+    }
+}
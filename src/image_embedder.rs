@@ -2,10 +2,12 @@ use anyhow::{Context, Error, Result};
 use reqwest::Client as ReqwestClient;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::env;
 use url::Url;
 
-use crate::cache::Cache;
+use crate::{
+    cache::Cache,
+    image_renderer::{renderers_from_env, ImageFormat, ImageRenderer, RenderedImage, SourceImage},
+};
 use lesswrong_api::Post;
 
 /// Result of attempting to embed an image
@@ -26,47 +28,52 @@ pub struct ImageEmbedding {
     pub old_url: String,
     /// Raw image bytes
     pub image_bytes: Vec<u8>,
+    /// Format of `image_bytes`, only meaningful once the image is downloaded
+    pub format: ImageFormat,
+}
+
+impl ImageEmbedding {
+    /// File name of the image inside the epub
+    pub fn file_name(&self) -> String {
+        format!("{}.{}", self.id, self.format.extension())
+    }
 }
 
 /// Component to handle fetching and embedding images
 pub struct ImageEmbedder {
-    client: ReqwestClient,
     cache_images: Cache<Vec<u8>>,
+    /// downloads the images for the renderers
+    client: ReqwestClient,
+    /// tried in order until one of them succeeds
+    renderers: Vec<Box<dyn ImageRenderer>>,
 }
 
 impl Default for ImageEmbedder {
     fn default() -> Self {
-        Self {
-            client: ReqwestClient::new(),
-            cache_images: Cache::new("images"),
-        }
+        Self::new(renderers_from_env())
     }
 }
 
-struct CloudflareCredentials {
-    key: String,
-    account_id: String,
-}
-
 impl ImageEmbedder {
-    fn get_credentials(&self) -> Option<CloudflareCredentials> {
-        let key = env::var("OPTIONAL_CLOUDFLARE_API_KEY").ok()?;
-        let account_id = env::var("OPTIONAL_CLOUDFLARE_ACCOUNT_ID").ok()?;
-        Some(CloudflareCredentials { key, account_id })
+    pub fn new(renderers: Vec<Box<dyn ImageRenderer>>) -> Self {
+        Self::with_cache(renderers, Cache::new("images"))
     }
 
-    pub fn supports_inlining_images(&self) -> bool {
-        self.get_credentials().is_some()
+    /// Caches the rendered images in `cache_images` instead of `.cache/images`
+    pub fn with_cache(
+        renderers: Vec<Box<dyn ImageRenderer>>,
+        cache_images: Cache<Vec<u8>>,
+    ) -> Self {
+        Self {
+            cache_images,
+            client: ReqwestClient::new(),
+            renderers,
+        }
     }
 
-    pub fn embed_image(
-        &self,
-        post: &Post,
-        image_url: &str,
-        image_alt: &Option<String>,
-    ) -> Result<EmbeddingResult, Error> {
+    pub fn embed_image(&self, post: &Post, image_url: &str) -> Result<EmbeddingResult, Error> {
         // Handle relative URLs by joining with the post's page URL
-        let absolute_url = if image_url.starts_with("http") {
+        let absolute_url = if image_url.starts_with("http") || image_url.starts_with("data:") {
             image_url.to_string()
         } else {
             let base_url =
@@ -79,33 +86,6 @@ impl ImageEmbedder {
             joined_url.to_string()
         };
 
-        // If Cloudflare credentials are not set, return a text link
-        if !self.supports_inlining_images() {
-            // Extract file extension to check if it's an SVG
-            let img_extension = absolute_url
-                .split('.')
-                .next_back()
-                .unwrap_or("")
-                .to_lowercase();
-            // Create anchor text for fallback
-            let anchor_text = if let Some(image_alt) = image_alt {
-                image_alt
-            } else {
-                image_url
-            };
-
-            // Determine prefix based on image type
-            let prefix = if img_extension.contains("svg") {
-                "Unsupported SVG image: "
-            } else {
-                "Image: "
-            };
-            return Ok(EmbeddingResult::Text(format!(
-                "<a href=\"{}\">{}{}</a>",
-                &absolute_url, prefix, &anchor_text
-            )));
-        }
-
         // Create a hash of the absolute URL for caching purposes
         // this also prevents using weird urls for local file system names
         let mut hasher = Sha256::new();
@@ -118,69 +98,70 @@ impl ImageEmbedder {
             id,
             old_url: absolute_url,
             image_bytes: vec![],
+            format: ImageFormat::default(),
         }))
     }
 
+    /// Fallback for images that can't be embedded: a link to the image
+    pub fn link_image(
+        &self,
+        absolute_url: &str,
+        image_url: &str,
+        image_alt: &Option<String>,
+    ) -> EmbeddingResult {
+        let img_extension = absolute_url
+            .split('.')
+            .next_back()
+            .unwrap_or("")
+            .to_lowercase();
+        // Create anchor text for fallback
+        let anchor_text = if let Some(image_alt) = image_alt {
+            image_alt
+        } else {
+            image_url
+        };
+
+        // Determine prefix based on image type
+        let prefix = if img_extension.contains("svg") {
+            "Unsupported SVG image: "
+        } else {
+            "Image: "
+        };
+        EmbeddingResult::Text(format!(
+            "<a href=\"{}\">{}{}</a>",
+            absolute_url, prefix, anchor_text
+        ))
+    }
+
+    /// Renders the image with the first renderer that can handle it. Rendered images are cached
     pub async fn download_image(&self, image_embedding: &mut ImageEmbedding) -> Result<(), Error> {
         if let Some(cached) = self.cache_images.get(&image_embedding.id)? {
+            // images cached before formats were tracked are all Cloudflare pngs
+            image_embedding.format = ImageFormat::sniff(&cached).unwrap_or_default();
             image_embedding.image_bytes = cached;
             return Ok(());
         }
 
-        // at this point credentials should always be available, as otherwise download_image is skipped
-        let CloudflareCredentials {
-            key: cloudflare_key,
-            account_id: cloudflare_account_id,
-        } = self
-            .get_credentials()
-            .expect("no Cloudflare credentials available");
-
-        // Construct the HTML to render with Cloudflare. by default html and body have padding and the image is offset
-        let html = format!("{}<img src=\"{}\">", "<style>* { margin: 0; padding: 0; } body { margin: 0; padding: 0; overflow: hidden; } img { display: block; width: 100%; height: auto; }</style>", image_embedding.old_url);
-        println!("HTML: {}", html);
-
-        let response = self
-            .client
-            .post(format!(
-                "https://api.cloudflare.com/client/v4/accounts/{}/browser-rendering/screenshot",
-                cloudflare_account_id
-            ))
-            .header("Authorization", format!("Bearer {}", cloudflare_key))
-            .header("Content-Type", "application/json")
-            .json(&serde_json::json!({
-                "html": html,
-                "screenshotOptions": {
-                    "omitBackground": false,
-                    "fullPage": true
-                },
-                // fix for issue: if we just take a normal screenshot the viewport is larger than the actual image and we get large margins around it. instead, set viewport height to 1 and fullPage to true to perfectly capture the full image height only.
-                "viewport": {
-                    "width": 640,
-                    "height": 1
+        // the renderers share the download
+        let image = SourceImage::new(self.client.clone(), &image_embedding.old_url);
+        let mut errors = vec![];
+        for renderer in &self.renderers {
+            match renderer.render(&image).await {
+                Ok(RenderedImage { bytes, format }) => {
+                    // Cache the image bytes
+                    self.cache_images.set(&image_embedding.id, &bytes)?;
+                    image_embedding.image_bytes = bytes;
+                    image_embedding.format = format;
+                    return Ok(());
                 }
-            }))
-            .send()
-            .await
-            .context("Failed to send request to Cloudflare API")?;
-
-        if !response.status().is_success() {
-            return Err(anyhow::anyhow!(
-                "Cloudflare API returned error: {}\n{}",
-                response.status(),
-                response.text().await?
-            ));
+                Err(e) => errors.push(format!("{}: {:#}", renderer.name(), e)),
+            }
         }
 
-        let image_bytes = response
-            .bytes()
-            .await
-            .context("Failed to get image bytes from Cloudflare API")?
-            .to_vec();
-
-        // Cache the image bytes
-        self.cache_images.set(&image_embedding.id, &image_bytes)?;
-        image_embedding.image_bytes = image_bytes;
-
-        Ok(())
+        Err(anyhow::anyhow!(
+            "No renderer could embed image {} ({})",
+            image_embedding.old_url,
+            errors.join("; ")
+        ))
     }
 }
@@ -0,0 +1,378 @@
+use anyhow::{Context, Error, Result};
+use async_trait::async_trait;
+use reqwest::Client as ReqwestClient;
+use resvg::{tiny_skia, usvg};
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    sync::{Arc, OnceLock},
+};
+use tokio::sync::OnceCell;
+
+/// Image formats that can be embedded into the epub as they are
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ImageFormat {
+    #[default]
+    Png,
+    Jpeg,
+    Gif,
+}
+
+impl ImageFormat {
+    /// Determines the format from the image's magic bytes, the server's content type can't be trusted
+    pub fn sniff(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]) {
+            Some(Self::Png)
+        } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(Self::Jpeg)
+        } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+            Some(Self::Gif)
+        } else {
+            None
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+            Self::Gif => "image/gif",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Jpeg => "jpg",
+            Self::Gif => "gif",
+        }
+    }
+}
+
+/// An image in a format that can be embedded into the epub
+#[derive(Debug)]
+pub struct RenderedImage {
+    pub bytes: Vec<u8>,
+    pub format: ImageFormat,
+}
+
+impl RenderedImage {
+    /// Wraps the bytes if they are in an embeddable format
+    pub fn sniff(bytes: Vec<u8>) -> Option<Self> {
+        ImageFormat::sniff(&bytes).map(|format| Self { bytes, format })
+    }
+}
+
+/// The image at a URL, downloaded at most once however many renderers look at its bytes
+pub struct SourceImage {
+    client: ReqwestClient,
+    url: String,
+    download: OnceCell<Result<Vec<u8>, String>>,
+}
+
+impl SourceImage {
+    pub fn new(client: ReqwestClient, url: &str) -> Self {
+        Self {
+            client,
+            url: url.to_string(),
+            download: OnceCell::new(),
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Downloads the image on first use, a failed download is not repeated either
+    pub async fn bytes(&self) -> Result<&[u8]> {
+        self.download
+            .get_or_init(|| async {
+                download(&self.client, &self.url)
+                    .await
+                    .map_err(|e| format!("{:#}", e))
+            })
+            .await
+            .as_deref()
+            .map_err(|e| anyhow::anyhow!("{}", e))
+    }
+}
+
+/// Turns an image into an embeddable image.
+/// Renderers return an error for images they can't handle, so the next renderer can be tried.
+#[async_trait]
+pub trait ImageRenderer: Send + Sync {
+    /// Name used in log and error messages
+    fn name(&self) -> &'static str;
+
+    async fn render(&self, image: &SourceImage) -> Result<RenderedImage>;
+}
+
+/// How SVG images are turned into embeddable images, configured by `OPTIONAL_SVG_RENDERER`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SvgRenderer {
+    /// Rasterize them locally with resvg, works offline
+    Local,
+    /// Pixelize them with Cloudflare's screenshot API
+    Cloudflare,
+}
+
+/// Builds the renderers in the order they should be tried, based on the environment:
+/// direct download first, then the configured SVG renderer, then Cloudflare for any other format.
+pub fn renderers_from_env() -> Vec<Box<dyn ImageRenderer>> {
+    let cloudflare = CloudflareRenderer::from_env(ReqwestClient::new());
+
+    // Defaults to Cloudflare if credentials are available, otherwise to local rasterization
+    let svg_renderer = match env::var("OPTIONAL_SVG_RENDERER").ok().as_deref() {
+        Some("local") => SvgRenderer::Local,
+        Some("cloudflare") => SvgRenderer::Cloudflare,
+        _ if cloudflare.is_some() => SvgRenderer::Cloudflare,
+        _ => SvgRenderer::Local,
+    };
+
+    let mut renderers: Vec<Box<dyn ImageRenderer>> = vec![Box::new(DirectDownloadRenderer)];
+    if svg_renderer == SvgRenderer::Local {
+        renderers.push(Box::new(LocalSvgRenderer));
+    }
+    if let Some(cloudflare) = cloudflare {
+        renderers.push(Box::new(cloudflare));
+    }
+    renderers
+}
+
+async fn download(client: &ReqwestClient, url: &str) -> Result<Vec<u8>, Error> {
+    // e.g. the locally rendered formulas
+    if let Some(data) = url.strip_prefix("data:") {
+        return decode_data_url(data);
+    }
+
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .with_context(|| format!("Failed to download image {}", url))?;
+
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "Downloading image {} returned error: {}",
+            url,
+            response.status()
+        ));
+    }
+
+    Ok(response
+        .bytes()
+        .await
+        .with_context(|| format!("Failed to get image bytes of {}", url))?
+        .to_vec())
+}
+
+/// The bytes of a percent-encoded `data:` URL, without the `data:` scheme
+fn decode_data_url(data: &str) -> Result<Vec<u8>, Error> {
+    let (media_type, data) = data.split_once(',').context("Data URL has no data")?;
+    if media_type.ends_with(";base64") {
+        return Err(anyhow::anyhow!("Base64 data URLs are not supported"));
+    }
+    let data = data.as_bytes();
+    let mut bytes = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        let hex = data
+            .get(i + 1..i + 3)
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (data[i], hex) {
+            (b'%', Some(byte)) => {
+                bytes.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                bytes.push(byte);
+                i += 1;
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+/// Embeds downloaded PNG, JPEG and GIF images as they are
+pub struct DirectDownloadRenderer;
+
+#[async_trait]
+impl ImageRenderer for DirectDownloadRenderer {
+    fn name(&self) -> &'static str {
+        "direct download"
+    }
+
+    async fn render(&self, image: &SourceImage) -> Result<RenderedImage> {
+        let bytes = image.bytes().await?;
+        RenderedImage::sniff(bytes.to_vec())
+            .with_context(|| format!("Image {} is not a PNG, JPEG or GIF image", image.url()))
+    }
+}
+
+// width of locally rasterized SVGs, twice the Cloudflare viewport so text stays sharp on high-dpi e-readers
+const SVG_RENDER_WIDTH: u32 = 1280;
+
+fn looks_like_svg(bytes: &[u8]) -> bool {
+    let head = String::from_utf8_lossy(&bytes[..bytes.len().min(1024)]);
+    head.contains("<svg")
+}
+
+fn rasterize_svg(svg: &[u8]) -> Result<Vec<u8>, Error> {
+    rasterize_svg_to_width(svg, SVG_RENDER_WIDTH)
+}
+
+/// Options for parsing SVGs with the system fonts, which are only loaded once
+pub fn svg_options() -> usvg::Options<'static> {
+    static FONTS: OnceLock<Arc<usvg::fontdb::Database>> = OnceLock::new();
+    let fonts = FONTS.get_or_init(|| {
+        let mut fonts = usvg::fontdb::Database::new();
+        fonts.load_system_fonts();
+        Arc::new(fonts)
+    });
+    usvg::Options {
+        fontdb: fonts.clone(),
+        ..usvg::Options::default()
+    }
+}
+
+/// Rasterizes the SVG to a PNG of the given width, keeping the aspect ratio. Text is rendered with the system fonts
+pub fn rasterize_svg_to_width(svg: &[u8], width: u32) -> Result<Vec<u8>, Error> {
+    let tree = usvg::Tree::from_data(svg, &svg_options()).context("Failed to parse SVG image")?;
+
+    let size = tree.size();
+    let scale = width as f32 / size.width();
+    let mut pixmap = tiny_skia::Pixmap::new(width, (size.height() * scale).ceil() as u32)
+        .context("SVG image has an invalid size")?;
+    // transparent backgrounds show up black on some e-readers
+    pixmap.fill(tiny_skia::Color::WHITE);
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    pixmap
+        .encode_png()
+        .context("Failed to encode rasterized SVG image")
+}
+
+/// Rasterizes downloaded SVG images to PNG locally
+pub struct LocalSvgRenderer;
+
+#[async_trait]
+impl ImageRenderer for LocalSvgRenderer {
+    fn name(&self) -> &'static str {
+        "local SVG rasterizer"
+    }
+
+    async fn render(&self, image: &SourceImage) -> Result<RenderedImage> {
+        let bytes = image.bytes().await?;
+        if !looks_like_svg(bytes) {
+            return Err(anyhow::anyhow!("Image {} is not an SVG image", image.url()));
+        }
+
+        Ok(RenderedImage {
+            bytes: rasterize_svg(bytes)?,
+            format: ImageFormat::Png,
+        })
+    }
+}
+
+/// Pixelizes any image with Cloudflare's screenshot API
+pub struct CloudflareRenderer {
+    client: ReqwestClient,
+    key: String,
+    account_id: String,
+}
+
+impl CloudflareRenderer {
+    /// Returns `None` if the Cloudflare credentials are not set
+    pub fn from_env(client: ReqwestClient) -> Option<Self> {
+        let key = env::var("OPTIONAL_CLOUDFLARE_API_KEY").ok()?;
+        let account_id = env::var("OPTIONAL_CLOUDFLARE_ACCOUNT_ID").ok()?;
+        Some(Self {
+            client,
+            key,
+            account_id,
+        })
+    }
+}
+
+#[async_trait]
+impl ImageRenderer for CloudflareRenderer {
+    fn name(&self) -> &'static str {
+        "Cloudflare screenshot"
+    }
+
+    async fn render(&self, image: &SourceImage) -> Result<RenderedImage> {
+        let url = image.url();
+        // Construct the HTML to render with Cloudflare. by default html and body have padding and the image is offset
+        let html = format!("{}<img src=\"{}\">", "<style>* { margin: 0; padding: 0; } body { margin: 0; padding: 0; overflow: hidden; } img { display: block; width: 100%; height: auto; }</style>", url);
+
+        let response = self
+            .client
+            .post(format!(
+                "https://api.cloudflare.com/client/v4/accounts/{}/browser-rendering/screenshot",
+                self.account_id
+            ))
+            .header("Authorization", format!("Bearer {}", self.key))
+            .header("Content-Type", "application/json")
+            .json(&serde_json::json!({
+                "html": html,
+                "screenshotOptions": {
+                    "omitBackground": false,
+                    "fullPage": true
+                },
+                // fix for issue: if we just take a normal screenshot the viewport is larger than the actual image and we get large margins around it. instead, set viewport height to 1 and fullPage to true to perfectly capture the full image height only.
+                "viewport": {
+                    "width": 640,
+                    "height": 1
+                }
+            }))
+            .send()
+            .await
+            .context("Failed to send request to Cloudflare API")?;
+
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "Cloudflare API returned error: {}\n{}",
+                response.status(),
+                response.text().await?
+            ));
+        }
+
+        let bytes = response
+            .bytes()
+            .await
+            .context("Failed to get image bytes from Cloudflare API")?
+            .to_vec();
+        // apparently Cloudflare's API returns all images in png format, even though their example says webp
+        RenderedImage::sniff(bytes).context("Cloudflare API returned an unsupported image format")
+    }
+}
+
+#[test]
+fn test_sniff_image_format() {
+    let cases = vec![
+        (&b"\x89PNG\r\n\x1a\n...."[..], Some(ImageFormat::Png)),
+        (&b"\xff\xd8\xff\xe0...."[..], Some(ImageFormat::Jpeg)),
+        (&b"GIF89a...."[..], Some(ImageFormat::Gif)),
+        (&b"<svg xmlns=\"http://www.w3.org/2000/svg\">"[..], None),
+        (&b"<!DOCTYPE html>"[..], None),
+    ];
+
+    for (input, expected) in cases {
+        assert_eq!(ImageFormat::sniff(input), expected);
+    }
+}
+
+#[test]
+fn test_rasterize_svg() -> Result<(), Error> {
+    let svg = br#"<?xml version="1.0"?><svg xmlns="http://www.w3.org/2000/svg" width="20" height="10"><rect width="10" height="10" fill="red"/></svg>"#;
+    assert!(looks_like_svg(svg));
+
+    let png = rasterize_svg(svg)?;
+    assert_eq!(ImageFormat::sniff(&png), Some(ImageFormat::Png));
+
+    Ok(())
+}
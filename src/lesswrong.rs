@@ -1,37 +1,137 @@
 use crate::cache::Cache;
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use lesswrong_api::{Comment, LessWrongApiClient, Post};
+use reqwest::Client as ReqwestClient;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
 use std::collections::HashMap;
+
+const GRAPHQL_URL: &str = "https://www.lesswrong.com/graphql";
+
+const SEQUENCE_QUERY: &str = r#"
+query Sequence($id: String) {
+  sequence(input: { selector: { _id: $id } }) {
+    result { _id title chapters { posts { _id } } }
+  }
+}"#;
+
+const COLLECTION_QUERY: &str = r#"
+query Collection($slug: String) {
+  collection(input: { selector: { slug: $slug } }) {
+    result { slug title books { title sequences { _id title chapters { posts { _id } } } } }
+  }
+}"#;
+
 pub struct LessWrongApi {
     client: LessWrongApiClient,
+    graphql_client: ReqwestClient,
     cache_post: Cache<Post>,
     cache_comments: Cache<HashMap<String, Comment>>,
+    cache_sequences: Cache<Sequence>,
+    cache_collections: Cache<Collection>,
 }
 pub struct PostWithComments {
     pub post: Post,
     pub comments: HashMap<String, Comment>,
 }
 
+/// A LessWrong sequence with its posts in reading order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sequence {
+    pub id: String,
+    pub title: String,
+    pub post_ids: Vec<String>,
+}
+
+/// A book of a collection, e.g. "Map and Territory" in "Rationality: A-Z"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Book {
+    pub title: Option<String>,
+    pub sequences: Vec<Sequence>,
+}
+
+/// A LessWrong collection like "rationality" or "codex"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Collection {
+    pub slug: String,
+    pub title: String,
+    pub books: Vec<Book>,
+}
+
+#[derive(Deserialize)]
+struct RawPostRef {
+    #[serde(rename = "_id")]
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct RawChapter {
+    posts: Vec<RawPostRef>,
+}
+
+#[derive(Deserialize)]
+struct RawSequence {
+    #[serde(rename = "_id")]
+    id: String,
+    title: String,
+    chapters: Vec<RawChapter>,
+}
+
+impl From<RawSequence> for Sequence {
+    fn from(raw: RawSequence) -> Self {
+        Self {
+            id: raw.id,
+            title: raw.title,
+            post_ids: raw
+                .chapters
+                .into_iter()
+                .flat_map(|chapter| chapter.posts)
+                .map(|post| post.id)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RawBook {
+    title: Option<String>,
+    sequences: Vec<RawSequence>,
+}
+
+#[derive(Deserialize)]
+struct RawCollection {
+    slug: String,
+    title: String,
+    books: Vec<RawBook>,
+}
+
 impl Default for LessWrongApi {
     fn default() -> Self {
         Self {
             client: LessWrongApiClient::default(),
+            graphql_client: ReqwestClient::new(),
             cache_post: Cache::new("posts"),
             cache_comments: Cache::new("comments"),
+            cache_sequences: Cache::new("sequences"),
+            cache_collections: Cache::new("collections"),
         }
     }
 }
 
 impl LessWrongApi {
+    /// A post without its comments, e.g. one that is only referenced by the book's posts
+    pub async fn get_post(&self, id: &str) -> Result<Post> {
+        if let Some(post) = self.cache_post.get(id)? {
+            return Ok(post);
+        }
+
+        let post = self.client.get_post(id).await?;
+        self.cache_post.set(id, &post)?;
+        Ok(post)
+    }
+
     pub async fn get_post_and_comments(&self, id: &str) -> Result<PostWithComments> {
-        let post = match self.cache_post.get(id)? {
-            Some(post) => post,
-            None => {
-                let post = self.client.get_post(id).await?;
-                self.cache_post.set(id, &post)?;
-                post
-            }
-        };
+        let post = self.get_post(id).await?;
 
         let comments = match self.cache_comments.get(id)? {
             Some(comments) => comments,
@@ -44,4 +144,84 @@ impl LessWrongApi {
 
         Ok(PostWithComments { post, comments })
     }
+
+    pub async fn get_sequence(&self, id: &str) -> Result<Sequence> {
+        if let Some(sequence) = self.cache_sequences.get(id)? {
+            return Ok(sequence);
+        }
+
+        let raw: RawSequence = self
+            .query(SEQUENCE_QUERY, json!({ "id": id }), "sequence")
+            .await
+            .with_context(|| format!("Failed to fetch sequence {}", id))?;
+        let sequence = Sequence::from(raw);
+
+        self.cache_sequences.set(id, &sequence)?;
+        Ok(sequence)
+    }
+
+    pub async fn get_collection(&self, slug: &str) -> Result<Collection> {
+        if let Some(collection) = self.cache_collections.get(slug)? {
+            return Ok(collection);
+        }
+
+        let raw: RawCollection = self
+            .query(COLLECTION_QUERY, json!({ "slug": slug }), "collection")
+            .await
+            .with_context(|| format!("Failed to fetch collection {}", slug))?;
+        let collection = Collection {
+            slug: raw.slug,
+            title: raw.title,
+            books: raw
+                .books
+                .into_iter()
+                .map(|book| Book {
+                    title: book.title,
+                    sequences: book.sequences.into_iter().map(Sequence::from).collect(),
+                })
+                .collect(),
+        };
+
+        self.cache_collections.set(slug, &collection)?;
+        Ok(collection)
+    }
+
+    /// Runs a single-document GraphQL query and deserializes `data.{field}.result`
+    async fn query<T: DeserializeOwned>(
+        &self,
+        query: &str,
+        variables: Value,
+        field: &str,
+    ) -> Result<T> {
+        let response = self
+            .graphql_client
+            .post(GRAPHQL_URL)
+            .json(&json!({ "query": query, "variables": variables }))
+            .send()
+            .await
+            .context("Failed to send request to LessWrong GraphQL API")?;
+
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "LessWrong GraphQL API returned error: {}\n{}",
+                response.status(),
+                response.text().await?
+            ));
+        }
+
+        let mut body: Value = response
+            .json()
+            .await
+            .context("Failed to parse LessWrong GraphQL response")?;
+        if let Some(errors) = body.get("errors") {
+            return Err(anyhow!("LessWrong GraphQL API returned errors: {}", errors));
+        }
+
+        let result = body
+            .pointer_mut(&format!("/data/{}/result", field))
+            .map(Value::take)
+            .filter(|result| !result.is_null())
+            .ok_or_else(|| anyhow!("LessWrong GraphQL API returned no {}", field))?;
+        serde_json::from_value(result).context("Failed to deserialize LessWrong GraphQL result")
+    }
 }
@@ -1,6 +1,21 @@
 pub mod ai;
 pub mod cache;
+pub mod chunking;
+pub mod comment_tree;
+pub mod cover;
 pub mod epub;
+pub mod flashcards;
+pub mod footnotes;
 pub mod image_embedder;
+pub mod image_renderer;
 pub mod lesswrong;
+pub mod locale;
+pub mod manifest;
+pub mod math;
+pub mod math_image;
+pub mod prompts;
+pub mod references;
+pub mod retry;
 pub mod sort_comments;
+pub mod summarizer;
+pub mod usage;
@@ -0,0 +1,90 @@
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::path::Path;
+
+// compiled in so there are always labels to fall back to
+const ENGLISH_LABELS: &str = include_str!("../epub_resources/locales/en.json");
+
+/// Language of the book: the labels of the templates, loaded from `epub_resources/locales/<language>.json`
+#[derive(Debug, Clone)]
+pub struct Locale {
+    /// language code, e.g. "de", used for `dc:language` and the `lang` attributes
+    pub language: String,
+    labels: Value,
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Self {
+            language: "en".to_string(),
+            labels: serde_json::from_str(ENGLISH_LABELS).unwrap(),
+        }
+    }
+}
+
+impl Locale {
+    /// Loads the labels of the language, falling back to English for labels missing from its file.
+    /// Languages without a file get English labels
+    pub fn load(language: &str) -> Result<Self> {
+        let mut locale = Self {
+            language: language.to_string(),
+            ..Self::default()
+        };
+        let path = Path::new("epub_resources/locales").join(format!("{}.json", language));
+        if !path.exists() {
+            println!(
+                "No labels for language \"{}\" in {:?}, using English labels",
+                language, path
+            );
+            // the prompts still ask for the language, by its code
+            locale.labels["language_name"] = Value::from(language);
+            return Ok(locale);
+        }
+
+        let json = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read labels {:?}", path))?;
+        let labels: Value = serde_json::from_str(&json)
+            .with_context(|| format!("Failed to parse labels {:?}", path))?;
+        if let (Some(merged), Some(labels)) = (locale.labels.as_object_mut(), labels.as_object()) {
+            merged.extend(labels.clone());
+        }
+        Ok(locale)
+    }
+
+    pub fn is_english(&self) -> bool {
+        self.language == "en"
+    }
+
+    /// English name of the language for the prompts, e.g. "German". The language code if unknown
+    pub fn language_name(&self) -> &str {
+        self.label("language_name")
+    }
+
+    /// All labels, passed to the templates as `labels`
+    pub fn labels(&self) -> &Value {
+        &self.labels
+    }
+
+    pub fn label<'a>(&'a self, key: &'a str) -> &'a str {
+        self.labels.get(key).and_then(Value::as_str).unwrap_or(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falls_back_to_english_labels() -> Result<()> {
+        let german = Locale::load("de")?;
+        assert_eq!(german.label("summary"), "Zusammenfassung");
+        assert_eq!(german.language_name(), "German");
+
+        let unknown = Locale::load("xx")?;
+        assert_eq!(unknown.label("summary"), "Summary");
+        assert_eq!(unknown.language_name(), "xx");
+        assert_eq!(unknown.language, "xx");
+
+        Ok(())
+    }
+}
@@ -1,10 +1,23 @@
-use clap::Parser;
+use chrono::Utc;
+use clap::{parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use futures::{stream, StreamExt, TryStreamExt};
+use lesswrong_api::Post;
 use lesswrong_sequences_highlights_epub::{
     ai::{AiClient, AnnotatedPostWithComments},
-    epub::Epub,
+    epub::{book_identifier, BookMetadata, Epub},
+    flashcards::anki_tsv,
     lesswrong::LessWrongApi,
+    locale::Locale,
+    manifest::{BookManifest, OutputFormat},
+    math::MathRendering,
+    prompts::Prompts,
+    references::{linked_post_ids, most_referenced, ReferencedPost},
+    retry::RetryPolicy,
+    sort_comments::{CommentSelector, Ranking, Traversal},
+    summarizer::AiProvider,
+    usage::{print_report, PriceTable},
 };
-use std::path::PathBuf;
+use std::{collections::HashSet, path::PathBuf};
 
 #[derive(Parser, Debug)]
 #[clap(
@@ -13,136 +26,610 @@ use std::path::PathBuf;
     about = "Generate EPUB from LessWrong posts with AI summaries"
 )]
 struct Args {
-    /// Space-separated list of LessWrong post IDs
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Space-separated list of LessWrong post IDs. Without post IDs, sequences or collection the Sequences Highlights are built from `books/sequences-highlights.toml`
     #[clap(value_parser, num_args = 0..)]
     post_ids: Vec<String>,
 
+    /// LessWrong sequence ID to build the book from. Can be repeated, each sequence becomes a section of the book
+    #[clap(long, conflicts_with_all = ["post_ids", "collection"])]
+    sequence: Vec<String>,
+
+    /// LessWrong collection slug to build the book from, e.g. "rationality" or "codex"
+    #[clap(long, conflicts_with = "post_ids")]
+    collection: Option<String>,
+
     /// Output file path
     #[clap(short, long)]
     output: Option<PathBuf>,
+
+    /// Title of the book. Defaults to the title of the sequence, collection or first post
+    #[clap(long)]
+    title: Option<String>,
+
+    /// Author of the book, can be repeated. Defaults to the author of the first post
+    #[clap(long)]
+    author: Vec<String>,
+
+    /// Publisher shown on a title page at the front of the book
+    #[clap(long)]
+    publisher: Option<String>,
+
+    /// Description of the book for e-readers and stores
+    #[clap(long)]
+    description: Option<String>,
+
+    /// Subject tag of the book, e.g. "Rationality". Can be repeated
+    #[clap(long)]
+    subject: Vec<String>,
+
+    /// PNG, JPEG or GIF cover image. The Sequences Highlights use `epub_resources/cover.jpg`, other books get a generated cover with their title, authors and date
+    #[clap(long)]
+    cover: Option<PathBuf>,
+
+    /// Number of posts that are fetched and summarized (and images downloaded) concurrently
+    #[clap(short, long, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: u16,
+
+    /// LLM backend for the summaries: openai (any OpenAI-compatible API), ollama or stub. Defaults to `AI_PROVIDER` or openai
+    #[clap(long)]
+    ai_provider: Option<AiProvider>,
+
+    /// Directory with the Handlebars prompt templates `summarize_post.hbs` and `summarize_comments.hbs`
+    #[clap(long, default_value = "epub_resources/prompts")]
+    prompts_dir: PathBuf,
+
+    /// Approximate length of the post and comments summaries in words
+    #[clap(long, default_value_t = 200)]
+    summary_words: usize,
+
+    /// How often a failed LLM request is retried, with exponential backoff, before the summary is marked unavailable
+    #[clap(long, default_value_t = 3)]
+    max_retries: u32,
+
+    /// Maximum number of LLM requests started per minute, unlimited by default
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    requests_per_minute: Option<u32>,
+
+    /// Add key takeaways to every chapter and a glossary of the terms the posts define to the end of the book
+    #[clap(long)]
+    key_takeaways: bool,
+
+    /// Add review questions to every chapter, with an answers appendix and an Anki-importable TSV file next to the EPUB
+    #[clap(long)]
+    review_questions: bool,
+
+    /// Number of top comment threads rendered after each chapter, with their replies
+    #[clap(long, default_value_t = 0)]
+    top_comments: usize,
+
+    /// Order of the summarized and rendered comments: depth-first (threads in reading order) or breadth-first (top-level comments first)
+    #[clap(long, default_value = "depth-first")]
+    comment_order: Traversal,
+
+    /// Ranking of replies to the same comment: score or recent (score decayed by age)
+    #[clap(long, default_value = "score")]
+    comment_ranking: Ranking,
+
+    /// Skip comments below this karma, together with their replies
+    #[clap(long)]
+    comment_min_karma: Option<f64>,
+
+    /// Skip the post author's comments, replies to them take their place in the thread
+    #[clap(long)]
+    exclude_author_replies: bool,
+
+    /// Maximum number of comments per thread, including the top-level comment
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    comments_per_thread: Option<u32>,
+
+    /// Maximum estimated tokens of the comments sent to the LLM for the comments summary
+    #[clap(long)]
+    comment_token_budget: Option<usize>,
+
+    /// Add an appendix summarizing the n posts outside the book that its posts link to most often. Links to them point to the appendix
+    #[clap(long, default_value_t = 0)]
+    referenced_posts: usize,
+
+    /// How LaTeX formulas are rendered: mathml (with images for formulas that can't be converted) or image, for readers without MathML support like Kindle
+    #[clap(long, default_value = "mathml")]
+    math: MathRendering,
+
+    /// Language of the book, e.g. "de" or "es". Summaries are written in it and the labels are taken from `epub_resources/locales/<language>.json`
+    #[clap(long, default_value = "en")]
+    language: String,
+
+    /// Also translate the post bodies into `--language`, section by section
+    #[clap(long, requires = "language")]
+    translate_body: bool,
+
+    /// JSON file with the prices of the models in USD per million tokens, to estimate the cost in the usage report
+    #[clap(long)]
+    price_table: Option<PathBuf>,
+
+    /// Only fetch the posts and estimate the tokens and cost of the summaries, without calling the LLM or writing the EPUB
+    #[clap(long)]
+    dry_run: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Build the book described by a TOML manifest, see `books/sequences-highlights.toml`. Options given on the command line take precedence over it
+    Build { manifest: PathBuf },
+}
+
+/// Built when no posts, sequences or collection are given
+const DEFAULT_MANIFEST: &str = "books/sequences-highlights.toml";
+
+/// Replies rendered per thread with `--top-comments` unless `--comments-per-thread` is given
+const MAX_COMMENTS_PER_THREAD: usize = 10;
+
+/// An entry of the book in reading order. Sections group the posts following them, see `Epub::begin_section`
+enum BookItem<T> {
+    Section {
+        title: String,
+        level: i32,
+        intro: Option<String>,
+    },
+    Post(T),
+}
+
+/// Takes the options the command line doesn't give explicitly from the manifest
+fn apply_manifest(
+    args: &mut Args,
+    matches: &ArgMatches,
+    manifest: &BookManifest,
+) -> anyhow::Result<()> {
+    // options with a default value are only overridden by the manifest if they were left at it
+    let explicit = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+
+    if args.title.is_none() {
+        args.title = Some(manifest.title.clone());
+    }
+    if args.author.is_empty() {
+        args.author.clone_from(&manifest.authors);
+    }
+    if args.subject.is_empty() {
+        args.subject.clone_from(&manifest.subjects);
+    }
+    args.publisher = args.publisher.take().or(manifest.publisher.clone());
+    args.description = args.description.take().or(manifest.description.clone());
+    args.cover = args.cover.take().or(manifest.cover.clone());
+    args.output = args.output.take().or(manifest.output.clone());
+    if let (false, Some(language)) = (explicit("language"), &manifest.language) {
+        args.language.clone_from(language);
+    }
+
+    let ai = &manifest.ai;
+    if let (None, Some(provider)) = (args.ai_provider, &ai.provider) {
+        args.ai_provider = Some(provider.parse().map_err(|e: String| anyhow::anyhow!(e))?);
+    }
+    if let (false, Some(prompts_dir)) = (explicit("prompts_dir"), &ai.prompts_dir) {
+        args.prompts_dir.clone_from(prompts_dir);
+    }
+    if let (false, Some(summary_words)) = (explicit("summary_words"), ai.summary_words) {
+        args.summary_words = summary_words;
+    }
+    if let (false, Some(top_comments)) = (explicit("top_comments"), ai.top_comments) {
+        args.top_comments = top_comments;
+    }
+    if let (false, Some(referenced_posts)) = (explicit("referenced_posts"), ai.referenced_posts) {
+        args.referenced_posts = referenced_posts;
+    }
+    // flags can only be switched on, an unset flag leaves the manifest's choice
+    args.key_takeaways |= ai.key_takeaways;
+    args.review_questions |= ai.review_questions;
+    args.translate_body |= ai.translate_body;
+    Ok(())
+}
+
+/// Fetches a post with its comments and creates the post and comments summaries
+async fn annotate_post(
+    api: &LessWrongApi,
+    ai: &AiClient,
+    id: &str,
+) -> anyhow::Result<AnnotatedPostWithComments> {
+    let mut post = api.get_post_and_comments(id).await?;
+    println!("Retrieved post: {}", post.post.title);
+    println!("Comments count: {}", post.comments.len());
+
+    println!(
+        "Creating POST and COMMENTS summaries for {}",
+        &post.post.title
+    );
+    let (summary, comments_summary, key_takeaways, review_questions, body) = tokio::try_join!(
+        ai.summarize_post(&post.post),
+        ai.summarize_comments(&post),
+        ai.key_takeaways(&post.post),
+        ai.review_questions(&post.post),
+        ai.translate_post(&post.post)
+    )?;
+    post.post.content_markdown = body;
+
+    Ok(AnnotatedPostWithComments::new(
+        post,
+        summary,
+        comments_summary,
+        key_takeaways,
+        review_questions,
+    ))
+}
+
+/// Fetches the `max_posts` posts outside the book that the book's posts link to most often.
+/// `linked` holds the post ids each post of the book links to. Posts that fail to load are left out
+async fn fetch_referenced_posts(
+    api: &LessWrongApi,
+    linked: &[Vec<String>],
+    book_post_ids: &HashSet<&str>,
+    max_posts: usize,
+    jobs: usize,
+) -> Vec<Post> {
+    stream::iter(most_referenced(linked, book_post_ids, max_posts))
+        .map(|id| async move {
+            match api.get_post(&id).await {
+                Ok(post) => {
+                    println!("Retrieved referenced post: {}", post.title);
+                    Some(post)
+                }
+                Err(e) => {
+                    println!("Failed to fetch referenced post {}: {:#}", id, e);
+                    None
+                }
+            }
+        })
+        .buffered(jobs)
+        .filter_map(|post| async { post })
+        .collect()
+        .await
+}
+
+/// The post ids the post's body links to
+fn post_links(post: &Post) -> Vec<String> {
+    linked_post_ids(&markdown::to_html(&post.content_markdown))
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    dotenv::dotenv()?;
+    // a .env file is optional, e.g. for offline builds with the stub AI provider
+    dotenv::dotenv().ok();
 
-    let args = Args::parse();
-    // if no post ids provided, build the sequences
-    let is_sequences = args.post_ids.is_empty();
-
-    let post_ids = if is_sequences {
-        SEQUENCES_POST_IDS
-    } else {
-        &args.post_ids.iter().map(|s| s.as_str()).collect::<Vec<_>>()
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches)?;
+    let manifest_path = match &args.command {
+        Some(Command::Build { manifest }) => {
+            if !args.post_ids.is_empty() || !args.sequence.is_empty() || args.collection.is_some() {
+                return Err(anyhow::anyhow!(
+                    "A manifest can't be combined with post IDs, sequences or a collection"
+                ));
+            }
+            Some(manifest.clone())
+        }
+        None if args.post_ids.is_empty()
+            && args.sequence.is_empty()
+            && args.collection.is_none() =>
+        {
+            Some(PathBuf::from(DEFAULT_MANIFEST))
+        }
+        None => None,
+    };
+    let manifest = manifest_path
+        .map(|path| BookManifest::load(&path))
+        .transpose()?;
+    if let Some(manifest) = &manifest {
+        apply_manifest(&mut args, &matches, manifest)?;
+    }
+    // `--review-questions` always writes them for Anki, review questions enabled by a manifest only if it lists the format
+    let (write_epub, write_anki) = match &manifest {
+        Some(manifest) => (
+            manifest.formats.contains(&OutputFormat::Epub),
+            manifest.formats.contains(&OutputFormat::Anki)
+                || matches.value_source("review_questions") == Some(ValueSource::CommandLine),
+        ),
+        None => (true, args.review_questions),
     };
 
+    // formulas are only sent to a remote image service if one is configured
+    let math_image_service = std::env::var("MATH_IMAGE_URL").ok();
+
     let api = LessWrongApi::default();
 
-    let mut posts = Vec::with_capacity(post_ids.len());
-    for id in post_ids {
-        let post = api.get_post_and_comments(id).await?;
-        println!("Retrieved post: {}", post.post.title);
-        println!("Comments count: {}", post.comments.len());
-        posts.push(post);
+    // title of the book and default output file name, if they are determined by the input
+    let mut book_title = None;
+    let mut book_file_name = None;
+    let mut items = Vec::new();
+    if let Some(manifest) = &manifest {
+        for section in &manifest.sections {
+            if let Some(title) = &section.title {
+                items.push(BookItem::Section {
+                    title: title.clone(),
+                    level: section.level,
+                    intro: section.intro.clone(),
+                });
+            }
+            items.extend(section.posts.iter().cloned().map(BookItem::Post));
+        }
+    } else if let Some(slug) = &args.collection {
+        let collection = api.get_collection(slug).await?;
+        println!("Retrieved collection: {}", collection.title);
+        for book in collection.books {
+            // untitled books don't get a section, their sequences move up one level
+            let sequence_level = match book.title {
+                Some(title) => {
+                    items.push(BookItem::Section {
+                        title,
+                        level: 1,
+                        intro: None,
+                    });
+                    2
+                }
+                None => 1,
+            };
+            for sequence in book.sequences {
+                items.push(BookItem::Section {
+                    title: sequence.title,
+                    level: sequence_level,
+                    intro: None,
+                });
+                items.extend(sequence.post_ids.into_iter().map(BookItem::Post));
+            }
+        }
+        book_title = Some(collection.title);
+        book_file_name = Some(collection.slug);
+    } else if !args.sequence.is_empty() {
+        for id in &args.sequence {
+            let sequence = api.get_sequence(id).await?;
+            println!("Retrieved sequence: {}", sequence.title);
+            if book_title.is_none() {
+                book_title = Some(sequence.title.clone());
+            }
+            items.push(BookItem::Section {
+                title: sequence.title,
+                level: 1,
+                intro: None,
+            });
+            items.extend(sequence.post_ids.into_iter().map(BookItem::Post));
+        }
+    } else {
+        items.extend(args.post_ids.iter().cloned().map(BookItem::Post));
     }
 
-    let ai = AiClient::default();
-    let mut annotated_posts = Vec::with_capacity(posts.len());
-    for post in posts.drain(..) {
-        println!("Creating POST summary for {}", &post.post.title);
-        let summary = ai.summarize_post(&post.post).await?;
-        println!("Creating COMMENTS summary for {}", &post.post.title);
-        let comments_summary = ai.summarize_comments(&post).await?;
-        annotated_posts.push(AnnotatedPostWithComments::new(
-            post,
-            summary,
-            comments_summary,
-        ));
+    let ai_provider = match args.ai_provider {
+        Some(provider) => provider,
+        None => AiProvider::from_env()?,
+    };
+    let locale = Locale::load(&args.language)?;
+    let mut prompts = Prompts::new(args.prompts_dir, args.summary_words);
+    // English prompts stay unchanged so their cached summaries are reused
+    if !locale.is_english() {
+        prompts.set_language(Some(locale.language_name().to_string()));
+    }
+    let comment_selector = CommentSelector {
+        traversal: args.comment_order,
+        ranking: args.comment_ranking,
+        max_per_thread: args.comments_per_thread.map(|n| n as usize),
+        min_karma: args.comment_min_karma,
+        exclude_post_author: args.exclude_author_replies,
+        ..CommentSelector::default()
+    };
+    let mut ai = AiClient::new(ai_provider.create_summarizer()?, prompts);
+    ai.set_comment_selector(CommentSelector {
+        max_tokens: args.comment_token_budget,
+        ..comment_selector
+    });
+    ai.set_retry_policy(RetryPolicy {
+        max_retries: args.max_retries,
+        ..RetryPolicy::default()
+    });
+    ai.set_key_takeaways(args.key_takeaways);
+    ai.set_review_questions(args.review_questions);
+    ai.set_translate_body(args.translate_body && !locale.is_english());
+    ai.set_requests_per_minute(args.requests_per_minute.map(|n| n as usize));
+    let prices = match &args.price_table {
+        Some(path) => PriceTable::load(path)?,
+        None => PriceTable::default(),
+    };
+    let jobs = usize::from(args.jobs);
+
+    if args.dry_run {
+        let post_ids = items.into_iter().filter_map(|item| match item {
+            BookItem::Post(id) => Some(id),
+            BookItem::Section { .. } => None,
+        });
+        let estimates = stream::iter(post_ids)
+            .map(|id| {
+                let (api, ai) = (&api, &ai);
+                async move {
+                    let post = api.get_post_and_comments(&id).await?;
+                    println!("Retrieved post: {}", post.post.title);
+                    Ok::<_, anyhow::Error>((id, post_links(&post.post), ai.estimate_usage(&post)?))
+                }
+            })
+            .buffered(jobs)
+            .try_collect::<Vec<_>>()
+            .await?;
+        let mut records = vec![];
+        let mut linked = vec![];
+        for (_, post_linked, post_records) in &estimates {
+            linked.push(post_linked.clone());
+            records.extend(post_records.iter().cloned());
+        }
+        if args.referenced_posts > 0 {
+            let book_post_ids = estimates
+                .iter()
+                .map(|(id, _, _)| id.as_str())
+                .collect::<HashSet<_>>();
+            let referenced =
+                fetch_referenced_posts(&api, &linked, &book_post_ids, args.referenced_posts, jobs)
+                    .await;
+            for post in &referenced {
+                records.extend(ai.estimate_post_usage(post)?);
+            }
+        }
+        print_report("Estimated token usage", ai.model(), &records, &prices);
+        return Ok(());
     }
 
+    // `buffered` runs up to `jobs` posts at once but yields them in input order, keeping the chapter order
+    let annotated_posts = stream::iter(items)
+        .map(|item| async {
+            Ok::<_, anyhow::Error>(match item {
+                BookItem::Section {
+                    title,
+                    level,
+                    intro,
+                } => BookItem::Section {
+                    title,
+                    level,
+                    intro,
+                },
+                BookItem::Post(id) => BookItem::Post(annotate_post(&api, &ai, &id).await?),
+            })
+        })
+        .buffered(jobs)
+        .try_collect::<Vec<_>>()
+        .await?;
+    let first_post = annotated_posts
+        .iter()
+        .find_map(|item| match item {
+            BookItem::Post(post) => Some(&post.post),
+            BookItem::Section { .. } => None,
+        })
+        .ok_or_else(|| anyhow::anyhow!("No posts to build the book from"))?;
+
     let mut epub = Epub::default();
+    epub.set_jobs(jobs);
+    epub.set_locale(locale);
+    epub.set_math_rendering(args.math);
+    epub.set_math_image_service(math_image_service);
+    let book_posts = annotated_posts
+        .iter()
+        .filter_map(|item| match item {
+            BookItem::Post(post) => Some(&post.post),
+            BookItem::Section { .. } => None,
+        })
+        .collect::<Vec<_>>();
+    epub.set_book_posts(book_posts.iter().copied());
+    if args.referenced_posts > 0 {
+        let book_post_ids = book_posts
+            .iter()
+            .map(|post| post.id.as_str())
+            .collect::<HashSet<_>>();
+        let linked = book_posts
+            .iter()
+            .map(|post| post_links(post))
+            .collect::<Vec<_>>();
+        let referenced =
+            fetch_referenced_posts(&api, &linked, &book_post_ids, args.referenced_posts, jobs)
+                .await;
+        println!(
+            "Creating summaries for {} referenced posts",
+            referenced.len()
+        );
+        let referenced = stream::iter(referenced)
+            .map(|post| {
+                let ai = &ai;
+                async move {
+                    let summary = ai.summarize_post(&post).await?;
+                    Ok::<_, anyhow::Error>(ReferencedPost { post, summary })
+                }
+            })
+            .buffered(jobs)
+            .try_collect::<Vec<_>>()
+            .await?;
+        epub.set_referenced_posts(referenced);
+    }
+    if args.top_comments > 0 {
+        epub.set_comment_selector(Some(CommentSelector {
+            max_comments: None,
+            max_threads: Some(args.top_comments),
+            max_per_thread: comment_selector
+                .max_per_thread
+                .or(Some(MAX_COMMENTS_PER_THREAD)),
+            ..comment_selector
+        }));
+    }
     // Determine output filename based on arguments and post IDs
     let output_path = match args.output {
         Some(path) => path,
         None => {
-            if is_sequences {
-                PathBuf::from("sequences-highlights.epub")
-            } else {
-                PathBuf::from(annotated_posts.first().unwrap().post.slug.clone() + ".epub")
-            }
+            let file_name = book_file_name.unwrap_or_else(|| first_post.slug.clone());
+            PathBuf::from(file_name + ".epub")
         }
     };
+    if write_anki {
+        let flashcards_path = output_path.with_extension("tsv");
+        std::fs::write(
+            &flashcards_path,
+            anki_tsv(annotated_posts.iter().filter_map(|item| match item {
+                BookItem::Post(post) => Some(post),
+                BookItem::Section { .. } => None,
+            })),
+        )?;
+        println!("Wrote review questions for Anki to {:?}", flashcards_path);
+    }
 
-    let (title, author) = if is_sequences {
-        (None, None)
-    } else {
-        (
-            Some(annotated_posts.first().unwrap().post.title.clone()),
-            Some(annotated_posts.first().unwrap().post.author.clone()),
-        )
-    };
-    epub.set_metadata(title, author, is_sequences)?;
+    if write_epub {
+        let post_ids = book_posts
+            .iter()
+            .map(|post| post.id.as_str())
+            .collect::<Vec<_>>();
+        epub.set_metadata(&BookMetadata {
+            title: args
+                .title
+                .or(book_title)
+                .unwrap_or_else(|| first_post.title.clone()),
+            authors: if args.author.is_empty() {
+                vec![first_post.author.clone()]
+            } else {
+                args.author
+            },
+            publisher: args.publisher,
+            description: args.description,
+            subjects: args.subject,
+            cover: args.cover,
+            identifier: book_identifier(&post_ids),
+            date: Utc::now(),
+        })?;
+
+        for item in annotated_posts {
+            match item {
+                BookItem::Section {
+                    title,
+                    level,
+                    intro,
+                } => {
+                    epub.begin_section(&title, level, intro.as_deref())?;
+                }
+                BookItem::Post(post) => {
+                    epub.add_post(&post).await?;
+                }
+            }
+        }
+        epub.add_glossary()?;
+        epub.add_answers()?;
+        epub.add_referenced_posts()?;
 
-    for post in annotated_posts {
-        epub.add_post(&post).await?;
+        let output = epub.generate()?;
+        std::fs::write(output_path, output)?;
     }
 
-    let output = epub.generate()?;
-    std::fs::write(output_path, output)?;
+    print_report("Token usage", ai.model(), &ai.usage_records(), &prices);
+
+    let degraded = ai.degraded_summaries();
+    if !degraded.is_empty() {
+        println!(
+            "{} summaries were unavailable and replaced by a placeholder or left out:",
+            degraded.len()
+        );
+        for summary in degraded {
+            println!(
+                "  - {} summary of \"{}\" ({}): {}",
+                summary.kind, summary.post_title, summary.post_id, summary.error
+            );
+        }
+    }
 
     Ok(())
 }
-
-// Default post IDs for the sequences if none provided
-const SEQUENCES_POST_IDS: &[&str] = &[
-    "46qnWRSR7L2eyNbMA",
-    "RcZCwxFiZzE6X7nsv",
-    "PBRWb2Em5SNeWYwwB",
-    "fhEPnveFhb9tmd7Pe",
-    "5JDkW4MYXit2CquLs",
-    "3nZMgRTfFEfHp34Gb",
-    "wCqfCLs8z5Qw4GbKS",
-    "teaxCFgtmCQ3E9fy8",
-    "7ZqGiPHTpiDMwqMN2",
-    "34XxbRFe54FycoCDw",
-    "SFZoEBpLo9frSJGkc",
-    "HYWhKXRsMAyvRKRYz",
-    "TGux5Fhcd7GmTfNGC",
-    "dHQkDNMhj692ayx78",
-    "nYkMLFpx77Rz3uo9c",
-    "2MD3NMLBPCqPfnfre",
-    "dLJv2CoRCgeC2mPgj",
-    "CEGnJBHmkcwPTysb7",
-    "rmAbiEKQDpDnZzcRf",
-    "AdYdLP2sRqPMoe8fb",
-    "9weLK2AJ9JEt2Tt8f",
-    "a7n8GdKiAZRX86T5A",
-    "6s3xABaXKPdFwA3FS",
-    "fhojYBGGiYAFcryHZ",
-    "nj8JKFoLSMEmD3RGp",
-    "mnS2WYLCGJP2kQkRn",
-    "jiBFC7DcCrZjGmZnJ",
-    "5yFRd3cjLpm3Nd6Di",
-    "XTXWPQSEgoMkAupKt",
-    "QkX2bAkwG2EpGvNug",
-    "CPP2uLcaywEokFKQG",
-    "WQFioaudEH8R7fyhm",
-    "wzxneh7wxkdNYNbtB",
-    "xTyuQ3cgsPjifr7oj",
-    "5bJyRMZzwMov5u3hW",
-    "wustx45CPL5rZenuo",
-    "WBdvyyHLdxZSAMmoz",
-    "Mc6QcrsbH5NRXbCRX",
-    "895quRDaK6gR2rM82",
-    "2jp98zdLo898qExrr",
-    "kpRSCH7ALLcb6ucWM",
-    "ZTRiSNmeGQK8AkdN2",
-    "yA4gF5KrboK2m2Xu7",
-    "HLqWn5LASfhhArZ7w",
-    "sSqoEw9eRP2kPKLCz",
-    "SGR4GxFK7KmW7ckCB",
-    "pGvyqAQw6yqTjpKf4",
-    "ur9TCRnHJighHmLCW",
-    "DoLQN5ryZ9XkZjq5h",
-    "Nu3wa6npK4Ry66vFp",
-];
@@ -0,0 +1,210 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+use crate::{references::parse_post_link, summarizer::AiProvider};
+
+/// A book described by a TOML manifest, built with `build <manifest>`. See `books/sequences-highlights.toml`.
+/// Paths are relative to the working directory, like the paths of the command line
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BookManifest {
+    pub title: String,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    pub publisher: Option<String>,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub subjects: Vec<String>,
+    pub cover: Option<PathBuf>,
+    pub language: Option<String>,
+    /// the EPUB file, the Anki file gets the same name with a `.tsv` extension
+    pub output: Option<PathBuf>,
+    #[serde(default = "default_formats")]
+    pub formats: Vec<OutputFormat>,
+    #[serde(default)]
+    pub ai: AiOptions,
+    pub sections: Vec<ManifestSection>,
+}
+
+fn default_formats() -> Vec<OutputFormat> {
+    vec![OutputFormat::Epub]
+}
+
+/// Files written by a build
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Epub,
+    /// the review questions as Anki-importable TSV, needs `ai.review_questions`
+    Anki,
+}
+
+/// The AI options of the command line that a manifest can set
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AiOptions {
+    pub provider: Option<String>,
+    pub prompts_dir: Option<PathBuf>,
+    pub summary_words: Option<usize>,
+    #[serde(default)]
+    pub key_takeaways: bool,
+    #[serde(default)]
+    pub review_questions: bool,
+    #[serde(default)]
+    pub translate_body: bool,
+    pub top_comments: Option<usize>,
+    pub referenced_posts: Option<usize>,
+}
+
+/// Posts of the book in reading order, optionally grouped under a section title page
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestSection {
+    /// the posts of an untitled section follow the previous section without a title page of their own
+    pub title: Option<String>,
+    /// markdown shown on the section's title page
+    pub intro: Option<String>,
+    #[serde(default = "default_level")]
+    pub level: i32,
+    /// post IDs or LessWrong post URLs
+    pub posts: Vec<String>,
+}
+
+fn default_level() -> i32 {
+    1
+}
+
+/// The post ID of a manifest entry, either an ID or a URL like `https://www.lesswrong.com/posts/<id>/<slug>`
+fn post_id(entry: &str) -> Result<String> {
+    if let Some(link) = parse_post_link(entry) {
+        return Ok(link.post_id);
+    }
+    let entry = entry.trim();
+    if !entry.is_empty() && entry.chars().all(|c| c.is_ascii_alphanumeric()) {
+        Ok(entry.to_string())
+    } else {
+        Err(anyhow!(
+            "\"{}\" is neither a post ID nor a LessWrong post URL",
+            entry
+        ))
+    }
+}
+
+impl BookManifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        let toml = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read book manifest {:?}", path))?;
+        Self::parse(&toml).with_context(|| format!("Invalid book manifest {:?}", path))
+    }
+
+    /// Parses the manifest and normalizes its post URLs to IDs
+    pub fn parse(toml: &str) -> Result<Self> {
+        let mut manifest: Self = toml::from_str(toml)?;
+        for section in &mut manifest.sections {
+            section.posts = section
+                .posts
+                .iter()
+                .map(|entry| post_id(entry))
+                .collect::<Result<_>>()?;
+        }
+        if let Some(provider) = &manifest.ai.provider {
+            provider.parse::<AiProvider>().map_err(|e| anyhow!(e))?;
+        }
+        if manifest.formats.contains(&OutputFormat::Anki) && !manifest.ai.review_questions {
+            return Err(anyhow!(
+                "The anki output format needs `review_questions = true` in [ai]"
+            ));
+        }
+        // the nesting rule of `Epub::begin_section`, checked before any post is fetched
+        let mut level = 0;
+        for section in &manifest.sections {
+            let Some(title) = &section.title else {
+                continue;
+            };
+            if section.level < 1 || section.level > level + 1 {
+                return Err(anyhow!(
+                    "Section \"{}\" has level {}, expected 1 to {}",
+                    title,
+                    section.level,
+                    level + 1
+                ));
+            }
+            level = section.level;
+        }
+        if manifest.sections.iter().all(|s| s.posts.is_empty()) {
+            return Err(anyhow!("The book has no posts"));
+        }
+        Ok(manifest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_manifest_with_post_urls() -> Result<()> {
+        let manifest = BookManifest::parse(
+            r#"
+            title = "A Book"
+            authors = ["Someone"]
+            formats = ["epub", "anki"]
+
+            [ai]
+            review_questions = true
+            summary_words = 150
+
+            [[sections]]
+            posts = ["46qnWRSR7L2eyNbMA"]
+
+            [[sections]]
+            title = "Part One"
+            intro = "Why this part *matters*."
+            posts = ["https://www.lesswrong.com/posts/RcZCwxFiZzE6X7nsv/what-do-we-mean-by-rationality-1"]
+            "#,
+        )?;
+
+        assert_eq!(
+            manifest.formats,
+            vec![OutputFormat::Epub, OutputFormat::Anki]
+        );
+        assert_eq!(manifest.ai.summary_words, Some(150));
+        assert_eq!(manifest.sections[0].title, None);
+        assert_eq!(manifest.sections[1].level, 1);
+        assert_eq!(manifest.sections[1].posts, vec!["RcZCwxFiZzE6X7nsv"]);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_manifests() {
+        let book = |rest: &str| format!("title = \"A Book\"\n{}", rest);
+        // unknown keys are typos, not silently ignored options
+        assert!(
+            BookManifest::parse(&book("subtitle = \"x\"\n[[sections]]\nposts = [\"abc\"]"))
+                .is_err()
+        );
+        assert!(BookManifest::parse(&book(
+            "[[sections]]\nposts = [\"https://example.com/posts/abc\"]"
+        ))
+        .is_err());
+        assert!(BookManifest::parse(&book(
+            "formats = [\"anki\"]\n[[sections]]\nposts = [\"abc\"]"
+        ))
+        .is_err());
+        assert!(BookManifest::parse(&book("[[sections]]\nposts = []")).is_err());
+        // a level 2 section needs a level 1 section before it
+        assert!(BookManifest::parse(&book(
+            "[[sections]]\ntitle = \"Part\"\nlevel = 2\nposts = [\"abc\"]"
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn parses_the_example_manifest() -> Result<()> {
+        let manifest = BookManifest::load(Path::new("books/sequences-highlights.toml"))?;
+
+        assert_eq!(manifest.sections[0].posts.len(), 50);
+        Ok(())
+    }
+}
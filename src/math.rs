@@ -0,0 +1,255 @@
+use std::{str::FromStr, sync::OnceLock};
+
+use latex2mathml::{latex_to_mathml, DisplayStyle};
+use regex::{Captures, Regex};
+
+use crate::math_image::render_mathml;
+
+/// How the LaTeX formulas of the posts are rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MathRendering {
+    /// MathML for EPUB 3 readers, formulas that can't be converted become images
+    #[default]
+    MathMl,
+    /// Images rendered locally, for readers without MathML support like Kindle
+    Image,
+}
+
+impl FromStr for MathRendering {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mathml" => Ok(Self::MathMl),
+            "image" => Ok(Self::Image),
+            _ => Err(format!(
+                "unknown math rendering \"{}\", expected mathml or image",
+                s
+            )),
+        }
+    }
+}
+
+/// Whether the markdown contains `$inline$` math the way LaTeX is usually written: no space after the opening or before
+/// the closing dollar sign, which isn't followed by a digit. Text like "$5 for *this* and $10" contains none
+fn contains_inline_math(markdown: &str) -> bool {
+    static INLINE_MATH_RE: OnceLock<Regex> = OnceLock::new();
+    let inline_math_re = INLINE_MATH_RE.get_or_init(|| {
+        Regex::new(r"(?:^|[^\\$])\$[^\s$](?:[^$]*[^\s$\\])?\$(?:[^0-9$]|$)").unwrap()
+    });
+    inline_math_re.is_match(markdown)
+}
+
+/// Converts markdown to HTML like `markdown::to_html`, keeping `$inline$` and `$$display$$` math as
+/// `<code class="language-math math-inline">` and `<pre><code class="language-math math-display">` for `render_math`.
+/// Inline math is only parsed if the markdown contains any, otherwise dollar signs are plain text
+pub fn markdown_to_html(markdown: &str) -> String {
+    let options = markdown::Options {
+        parse: markdown::ParseOptions {
+            constructs: markdown::Constructs {
+                math_flow: true,
+                math_text: contains_inline_math(markdown),
+                ..markdown::Constructs::default()
+            },
+            ..markdown::ParseOptions::default()
+        },
+        ..markdown::Options::default()
+    };
+    // only MDX can fail to parse
+    markdown::to_html_with_options(markdown, &options).unwrap()
+}
+
+/// Renders the markdown between two dollar signs that turned out not to be a formula, keeping its surrounding spaces
+fn render_dollar_text(text: &str) -> String {
+    let trimmed = text.trim();
+    let html = markdown::to_html(trimmed);
+    let Some(inline_html) = html
+        .strip_prefix("<p>")
+        .and_then(|html| html.strip_suffix("</p>"))
+    else {
+        return escape_html(text);
+    };
+    let leading = &text[..text.len() - text.trim_start().len()];
+    let trailing = &text[text.trim_end().len()..];
+    format!("{}{}{}", leading, inline_html, trailing)
+}
+
+fn unescape_html(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn percent_encode(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// URL of the formula rendered as an image by the `image_service`, e.g. `https://latex.codecogs.com/png.image`,
+/// which gets the LaTeX as the query
+fn math_image_url(image_service: &str, latex: &str, display: DisplayStyle) -> String {
+    let latex = match display {
+        DisplayStyle::Block => format!("\\dpi{{200}} \\displaystyle {}", latex),
+        DisplayStyle::Inline => format!("\\dpi{{200}} {}", latex),
+    };
+    format!("{}?{}", image_service, percent_encode(latex.as_bytes()))
+}
+
+/// `<img>` of the formula rendered locally, embedded as a `data:` URL by the image pipeline.
+/// Sized in `em` so it scales with the text around it, inline formulas sit on the baseline
+fn formula_image(mathml: &str, latex: &str, class: &str) -> anyhow::Result<String> {
+    let image = render_mathml(mathml)?;
+    Ok(format!(
+        "<img class=\"{}\" src=\"data:image/png,{}\" alt=\"{}\" style=\"height: {:.2}em; vertical-align: -{:.2}em\" />",
+        class,
+        percent_encode(&image.png),
+        escape_html(latex),
+        image.height,
+        image.depth
+    ))
+}
+
+fn render_formula(
+    latex: &str,
+    display: DisplayStyle,
+    rendering: MathRendering,
+    image_service: Option<&str>,
+) -> String {
+    let (class, display_attribute) = match display {
+        DisplayStyle::Block => ("math-display", "block"),
+        DisplayStyle::Inline => ("math-inline", "inline"),
+    };
+    let mathml = latex_to_mathml(latex, display)
+        // the LaTeX source is kept for accessibility and readers that show it instead of the MathML
+        .map(|mathml| {
+            mathml.replacen(
+                "<math ",
+                &format!("<math alttext=\"{}\" ", escape_html(latex)),
+                1,
+            )
+        });
+    let mathml = match mathml {
+        Ok(mathml) if rendering == MathRendering::MathMl => return mathml,
+        Ok(mathml) => mathml,
+        Err(e) => {
+            println!("Failed to convert formula {} to MathML: {}", latex, e);
+            // the formulas are only sent to an image service if one is configured
+            if let Some(image_service) = image_service {
+                return format!(
+                    "<img class=\"{}\" src=\"{}\" alt=\"{}\" />",
+                    class,
+                    math_image_url(image_service, latex, display),
+                    escape_html(latex)
+                );
+            }
+            // otherwise the source is rendered, readers see what the author wrote
+            format!(
+                "<math display=\"{}\"><mtext>{}</mtext></math>",
+                display_attribute,
+                escape_html(latex)
+            )
+        }
+    };
+    match formula_image(&mathml, latex, class) {
+        Ok(image) => image,
+        Err(e) => {
+            println!("Failed to render formula {}: {:#}", latex, e);
+            mathml
+        }
+    }
+}
+
+/// Renders the math of HTML converted with `markdown_to_html` as MathML or as images, which are then embedded by the image pipeline.
+/// Formulas that can't be converted to MathML are rendered by the optional `image_service`, or locally as their LaTeX source.
+/// Inline math starting or ending with a space is no formula but text between two dollar signs, like "$5 and $10", and is kept as it was
+pub fn render_math(html: &str, rendering: MathRendering, image_service: Option<&str>) -> String {
+    static DISPLAY_RE: OnceLock<Regex> = OnceLock::new();
+    static INLINE_RE: OnceLock<Regex> = OnceLock::new();
+    let display_re = DISPLAY_RE.get_or_init(|| {
+        Regex::new(r#"(?s)<pre><code class="language-math math-display">(.*?)</code></pre>"#)
+            .unwrap()
+    });
+    let html = display_re.replace_all(html, |captures: &Captures| {
+        let latex = unescape_html(&captures[1]);
+        format!(
+            "<div class=\"math-display\">{}</div>",
+            render_formula(latex.trim(), DisplayStyle::Block, rendering, image_service)
+        )
+    });
+
+    let inline_re = INLINE_RE.get_or_init(|| {
+        Regex::new(r#"(?s)<code class="language-math math-inline">(.*?)</code>"#).unwrap()
+    });
+    inline_re
+        .replace_all(&html, |captures: &Captures| {
+            let latex = unescape_html(&captures[1]);
+            if latex.trim() != latex || latex.is_empty() {
+                return format!("${}$", render_dollar_text(&latex));
+            }
+            render_formula(&latex, DisplayStyle::Inline, rendering, image_service)
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_math_as_mathml() {
+        let html = markdown_to_html(
+            "Energy is $E = mc^2$.\n\n$$\na < b\n$$\n\nIt costs $5 for *this* and $10.",
+        );
+        let html = render_math(&html, MathRendering::MathMl, None);
+
+        assert!(html.contains(r#"<math alttext="E = mc^2" "#));
+        assert!(html.contains(r#"<div class="math-display"><math alttext="a &lt; b" "#));
+        assert!(html.contains("It costs $5 for <em>this</em> and $10."));
+        assert!(!html.contains("language-math"));
+    }
+
+    #[test]
+    fn keeps_dollar_amounts_as_text() {
+        let html = markdown_to_html("It costs $5 for *this* and $10.");
+
+        assert_eq!(html, "<p>It costs $5 for <em>this</em> and $10.</p>");
+    }
+
+    #[test]
+    fn renders_math_as_images() {
+        let html = render_math(&markdown_to_html("$x^2$"), MathRendering::Image, None);
+
+        assert!(html.starts_with(r#"<p><img class="math-inline" src="data:image/png,%89PNG"#));
+        assert!(html.contains(r#" alt="x^2" style="height: "#));
+    }
+
+    #[test]
+    fn sends_only_unconvertible_formulas_to_the_image_service() {
+        let service = Some("https://latex.codecogs.com/png.image");
+        let html = render_math(
+            &markdown_to_html("$x^2$ and $\\unsupported{x}$"),
+            MathRendering::Image,
+            service,
+        );
+
+        assert!(html.contains(r#"src="data:image/png,"#));
+        assert!(html.contains(
+            r#"<img class="math-inline" src="https://latex.codecogs.com/png.image?%5Cdpi%7B200%7D%20%5Cunsupported%7Bx%7D" alt="\unsupported{x}" />"#
+        ));
+    }
+}
@@ -0,0 +1,785 @@
+use anyhow::{Context, Result};
+use resvg::usvg::{self, roxmltree};
+
+use crate::image_renderer::{rasterize_svg_to_width, svg_options};
+
+// the serif fonts of the cover, formulas are set like the text
+const FONT_FAMILY: &str = "Georgia, 'DejaVu Serif', 'Liberation Serif', 'Noto Serif', serif";
+// font size of the SVG, its coordinates are in 1/FONT_SIZE em
+const FONT_SIZE: f32 = 100.0;
+// pixels per em of the PNG, so formulas stay sharp when e-readers scale them with the text
+const PIXELS_PER_EM: f32 = 48.0;
+// size of scripts, fraction parts in inline formulas and limits relative to their base
+const SCRIPT_SCALE: f32 = 0.71;
+// height of fraction bars and the middle of operators and fences above the baseline
+const AXIS_HEIGHT: f32 = 0.25;
+// extent of a line of text above and below the baseline, glyphs are only measured horizontally
+const ASCENT: f32 = 0.72;
+const DESCENT: f32 = 0.22;
+const RULE_THICKNESS: f32 = 0.05;
+// white space around the formula
+const PADDING: f32 = 0.05;
+
+/// A formula rendered to a PNG, with its size in `em` of the text around it
+pub struct FormulaImage {
+    pub png: Vec<u8>,
+    pub width: f32,
+    pub height: f32,
+    /// how far the image reaches below the baseline of the formula
+    pub depth: f32,
+}
+
+/// Renders MathML, like `latex_to_mathml` produces it, to a PNG with the system's serif font.
+/// Covers the layout of the common elements (scripts, fractions, roots, limits, accents, matrices and stretched fences),
+/// other elements are laid out as rows of their children
+pub fn render_mathml(mathml: &str) -> Result<FormulaImage> {
+    let document = roxmltree::Document::parse(mathml).context("Failed to parse MathML")?;
+    let math = document.root_element();
+    let style = Style {
+        size: 1.0,
+        display: math.attribute("display") == Some("block"),
+    };
+    let layout = layout_row(math, style)?;
+
+    let width = layout.width + 2.0 * PADDING;
+    let depth = layout.descent + PADDING;
+    let height = layout.ascent + PADDING + depth;
+    let png = rasterize_svg_to_width(
+        layout.to_svg(width, height).as_bytes(),
+        (width * PIXELS_PER_EM).ceil() as u32,
+    )?;
+    Ok(FormulaImage {
+        png,
+        width,
+        height,
+        depth,
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Style {
+    /// font size in em
+    size: f32,
+    /// display style: large operators and full size fractions
+    display: bool,
+}
+
+impl Style {
+    fn script(self) -> Self {
+        Self {
+            size: (self.size * SCRIPT_SCALE).max(0.5),
+            display: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Item {
+    Text {
+        x: f32,
+        y: f32,
+        size: f32,
+        italic: bool,
+        bold: bool,
+        text: String,
+    },
+    /// a horizontal line, `y` is its middle
+    Rule {
+        x: f32,
+        y: f32,
+        width: f32,
+        thickness: f32,
+    },
+    /// the sign of a root
+    Polyline {
+        points: Vec<(f32, f32)>,
+        thickness: f32,
+    },
+}
+
+/// Laid out part of a formula, in em with the origin on its baseline and `y` pointing down like in SVG
+#[derive(Debug, Default)]
+struct Layout {
+    width: f32,
+    ascent: f32,
+    descent: f32,
+    items: Vec<Item>,
+}
+
+impl Layout {
+    /// Places `other` with its origin at `(x, y)`
+    fn place(&mut self, other: Layout, x: f32, y: f32) {
+        self.width = self.width.max(x + other.width);
+        self.ascent = self.ascent.max(other.ascent - y);
+        self.descent = self.descent.max(other.descent + y);
+        self.items.extend(other.items.into_iter().map(|item| {
+            match item {
+                Item::Text {
+                    x: item_x,
+                    y: item_y,
+                    size,
+                    italic,
+                    bold,
+                    text,
+                } => Item::Text {
+                    x: item_x + x,
+                    y: item_y + y,
+                    size,
+                    italic,
+                    bold,
+                    text,
+                },
+                Item::Rule {
+                    x: item_x,
+                    y: item_y,
+                    width,
+                    thickness,
+                } => Item::Rule {
+                    x: item_x + x,
+                    y: item_y + y,
+                    width,
+                    thickness,
+                },
+                Item::Polyline { points, thickness } => Item::Polyline {
+                    points: points
+                        .into_iter()
+                        .map(|(px, py)| (px + x, py + y))
+                        .collect(),
+                    thickness,
+                },
+            }
+        }));
+    }
+
+    /// Appends `other` on the baseline
+    fn append(&mut self, other: Layout) {
+        let x = self.width;
+        self.place(other, x, 0.0);
+    }
+
+    fn rule(&mut self, x: f32, y: f32, width: f32, style: Style) {
+        let thickness = RULE_THICKNESS * style.size;
+        self.items.push(Item::Rule {
+            x,
+            y,
+            width,
+            thickness,
+        });
+        self.ascent = self.ascent.max(thickness / 2.0 - y);
+        self.descent = self.descent.max(y + thickness / 2.0);
+    }
+
+    fn to_svg(&self, width: f32, height: f32) -> String {
+        let mut svg = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w:.1}" height="{h:.1}" viewBox="0 0 {w:.1} {h:.1}"><g transform="translate({x:.1} {y:.1})">"#,
+            w = width * FONT_SIZE,
+            h = height * FONT_SIZE,
+            x = PADDING * FONT_SIZE,
+            y = (PADDING + self.ascent) * FONT_SIZE,
+        );
+        for item in &self.items {
+            match item {
+                Item::Text {
+                    x,
+                    y,
+                    size,
+                    italic,
+                    bold,
+                    text,
+                } => svg.push_str(&format!(
+                    r#"<text x="{:.1}" y="{:.1}" font-size="{:.1}" {} xml:space="preserve">{}</text>"#,
+                    x * FONT_SIZE,
+                    y * FONT_SIZE,
+                    size * FONT_SIZE,
+                    font_attributes(*italic, *bold),
+                    escape_xml(text)
+                )),
+                Item::Rule {
+                    x,
+                    y,
+                    width,
+                    thickness,
+                } => svg.push_str(&format!(
+                    r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}"/>"#,
+                    x * FONT_SIZE,
+                    (y - thickness / 2.0) * FONT_SIZE,
+                    width * FONT_SIZE,
+                    thickness * FONT_SIZE
+                )),
+                Item::Polyline { points, thickness } => svg.push_str(&format!(
+                    r#"<polyline points="{}" fill="none" stroke="black" stroke-width="{:.1}" stroke-linejoin="round"/>"#,
+                    points
+                        .iter()
+                        .map(|(x, y)| format!("{:.1},{:.1}", x * FONT_SIZE, y * FONT_SIZE))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                    thickness * FONT_SIZE
+                )),
+            }
+        }
+        svg.push_str("</g></svg>");
+        svg
+    }
+}
+
+fn font_attributes(italic: bool, bold: bool) -> String {
+    format!(
+        r#"font-family="{}" font-style="{}" font-weight="{}""#,
+        FONT_FAMILY,
+        if italic { "italic" } else { "normal" },
+        if bold { "bold" } else { "normal" }
+    )
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Width of the text in em at font size 1, measured with the fonts the SVG is rendered with
+fn advance(text: &str, italic: bool, bold: bool) -> Result<f32> {
+    // the position of a marker after the text is the text's width, including trailing spaces
+    let svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100"><text y="{}" font-size="{}" {} xml:space="preserve">{}|</text></svg>"#,
+        FONT_SIZE,
+        FONT_SIZE,
+        font_attributes(italic, bold),
+        escape_xml(text)
+    );
+    let tree = usvg::Tree::from_data(svg.as_bytes(), &svg_options())?;
+    tree.root()
+        .children()
+        .iter()
+        .find_map(|node| match node {
+            usvg::Node::Text(text) => text
+                .layouted()
+                .iter()
+                .flat_map(|span| &span.positioned_glyphs)
+                .last()
+                .map(|marker| marker.transform().tx / FONT_SIZE),
+            _ => None,
+        })
+        .context("No font found to render formulas")
+}
+
+fn text(text: &str, style: Style, italic: bool, bold: bool) -> Result<Layout> {
+    Ok(Layout {
+        width: advance(text, italic, bold)? * style.size,
+        ascent: ASCENT * style.size,
+        descent: DESCENT * style.size,
+        items: vec![Item::Text {
+            x: 0.0,
+            y: 0.0,
+            size: style.size,
+            italic,
+            bold,
+            text: text.to_string(),
+        }],
+    })
+}
+
+/// Text of the element, with the spaces MathML ignores collapsed
+fn element_text(node: roxmltree::Node) -> String {
+    node.descendants()
+        .filter(|n| n.is_text())
+        .filter_map(|n| n.text())
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+const LARGE_OPERATORS: &[&str] = &[
+    "∑", "∏", "∐", "∫", "∬", "∭", "∮", "⋃", "⋂", "⋁", "⋀", "⨁", "⨂",
+];
+const RELATIONS: &[&str] = &[
+    "=", "<", ">", "≤", "≥", "≠", "≈", "≡", "∼", "≃", "≅", "∝", "→", "←", "↔", "⇒", "⇐", "⇔", "↦",
+    "∈", "∉", "∋", "⊂", "⊆", "⊃", "⊇", "≪", "≫", ":=", "∣", "⊥", "≺", "≻", "⪯", "⪰",
+];
+const BINARY_OPERATORS: &[&str] = &[
+    "+", "−", "-", "±", "∓", "×", "·", "⋅", "÷", "∗", "∘", "∪", "∩", "∧", "∨", "⊕", "⊗", "∖",
+];
+
+fn is_large_operator(node: roxmltree::Node) -> bool {
+    node.has_tag_name("mo") && LARGE_OPERATORS.contains(&element_text(node).as_str())
+}
+
+/// Lays out one element
+fn layout(node: roxmltree::Node, style: Style) -> Result<Layout> {
+    let children = node
+        .children()
+        .filter(|n| n.is_element())
+        .collect::<Vec<_>>();
+    let child = |i: usize| -> Result<roxmltree::Node> {
+        children
+            .get(i)
+            .copied()
+            .with_context(|| format!("<{}> misses a child", node.tag_name().name()))
+    };
+    match node.tag_name().name() {
+        "mi" => {
+            let content = element_text(node);
+            let variant = node.attribute("mathvariant").unwrap_or_default();
+            // single letters are variables, longer identifiers like function names are upright
+            let italic = match variant {
+                "" => content.chars().count() == 1 && content.chars().all(char::is_alphabetic),
+                variant => variant.contains("italic"),
+            };
+            text(&content, style, italic, variant.contains("bold"))
+        }
+        "mn" | "mtext" | "ms" => text(&element_text(node), style, false, false),
+        "mo" => layout_operator(node, style, true),
+        "mspace" => Ok(Layout {
+            width: node.attribute("width").map_or(0.0, parse_em) * style.size,
+            ..Layout::default()
+        }),
+        "msup" => layout_scripts(layout(child(0)?, style)?, None, Some(child(1)?), style),
+        "msub" => layout_scripts(layout(child(0)?, style)?, Some(child(1)?), None, style),
+        "msubsup" => layout_scripts(
+            layout(child(0)?, style)?,
+            Some(child(1)?),
+            Some(child(2)?),
+            style,
+        ),
+        "munder" | "mover" | "munderover" => {
+            let base = child(0)?;
+            let (under, over) = match node.tag_name().name() {
+                "munder" => (Some(child(1)?), None),
+                "mover" => (None, Some(child(1)?)),
+                _ => (Some(child(1)?), Some(child(2)?)),
+            };
+            // limits of large operators are scripts in inline formulas
+            if is_large_operator(base) && !style.display {
+                return layout_scripts(layout(base, style)?, under, over, style);
+            }
+            layout_limits(layout(base, style)?, under, over, style)
+        }
+        "mfrac" => layout_fraction(child(0)?, child(1)?, node.attribute("linethickness"), style),
+        "msqrt" => Ok(layout_root(layout_row(node, style)?, None, style)),
+        "mroot" => {
+            let index = layout(child(1)?, style.script().script())?;
+            Ok(layout_root(layout(child(0)?, style)?, Some(index), style))
+        }
+        "mtable" => layout_table(node, style),
+        "mstyle" => {
+            let style = match node.attribute("displaystyle") {
+                Some(display) => Style {
+                    display: display == "true",
+                    ..style
+                },
+                None => style,
+            };
+            layout_row(node, style)
+        }
+        "mphantom" => {
+            let mut phantom = layout_row(node, style)?;
+            phantom.items.clear();
+            Ok(phantom)
+        }
+        // the annotations are alternative representations of the formula
+        "semantics" => layout(child(0)?, style),
+        "annotation" | "annotation-xml" | "none" | "mprescripts" => Ok(Layout::default()),
+        // mrow, merror, mpadded, menclose, ...
+        _ => layout_row(node, style),
+    }
+}
+
+/// An operator with the space around it. Relations and binary operators have no space in scripts
+/// or at the start of a row, where they are signs like in `-x`
+fn layout_operator(node: roxmltree::Node, style: Style, spaced: bool) -> Result<Layout> {
+    let op = element_text(node);
+    if is_large_operator(node) && style.display {
+        // larger and centered on the axis
+        let size = style.size * 1.4;
+        let mut operator = text(&op, Style { size, ..style }, false, false)?;
+        let shift = (ASCENT - DESCENT) / 2.0 * size - AXIS_HEIGHT * style.size;
+        operator.ascent -= shift;
+        operator.descent += shift;
+        for item in &mut operator.items {
+            if let Item::Text { y, .. } = item {
+                *y += shift;
+            }
+        }
+        let mut layout = Layout::default();
+        layout.append(operator);
+        layout.width += 0.1 * style.size;
+        return Ok(layout);
+    }
+
+    // invisible operators, the function application after e.g. `sin` is a thin space
+    if ('\u{2061}'..='\u{2064}').contains(&op.chars().next().unwrap_or_default()) {
+        let width = if op == "\u{2061}" {
+            0.167 * style.size
+        } else {
+            0.0
+        };
+        return Ok(Layout {
+            width,
+            ..Layout::default()
+        });
+    }
+    // a hyphen is a minus sign in formulas
+    let op = if op == "-" { "−".to_string() } else { op };
+
+    let (before, after) = if !spaced || style.size < 1.0 {
+        (0.0, if op == "," { 0.1 } else { 0.0 })
+    } else if RELATIONS.contains(&op.as_str()) {
+        (0.28, 0.28)
+    } else if BINARY_OPERATORS.contains(&op.as_str()) {
+        (0.22, 0.22)
+    } else if op == "," || op == ";" {
+        (0.0, 0.17)
+    } else {
+        (0.0, 0.0)
+    };
+    let mut layout = Layout {
+        width: before * style.size,
+        ..Layout::default()
+    };
+    layout.append(text(&op, style, false, false)?);
+    layout.width += after * style.size;
+    Ok(layout)
+}
+
+fn is_stretchy_fence(node: roxmltree::Node) -> bool {
+    node.has_tag_name("mo") && node.attribute("stretchy") == Some("true")
+}
+
+/// The element's children side by side, stretchy fences as high as the rest of the row
+fn layout_row(node: roxmltree::Node, style: Style) -> Result<Layout> {
+    let children = node
+        .children()
+        .filter(|n| n.is_element())
+        .collect::<Vec<_>>();
+    let mut parts = Vec::with_capacity(children.len());
+    for (i, child) in children.iter().enumerate() {
+        parts.push(match *child {
+            child if is_stretchy_fence(child) => None,
+            child if child.has_tag_name("mo") => Some(layout_operator(child, style, i > 0)?),
+            child => Some(layout(child, style)?),
+        });
+    }
+
+    // fences reach as far above and below the axis as the content
+    let axis = AXIS_HEIGHT * style.size;
+    let content = parts.iter().flatten();
+    let half_height = content
+        .map(|part| (part.ascent - axis).max(part.descent + axis))
+        .fold((ASCENT - AXIS_HEIGHT) * style.size, f32::max);
+    let mut row = Layout::default();
+    for (child, part) in children.into_iter().zip(parts) {
+        let part = match part {
+            Some(part) => part,
+            None => layout_fence(&element_text(child), half_height, style)?,
+        };
+        row.append(part);
+    }
+    Ok(row)
+}
+
+/// A fence scaled to cover `half_height` above and below the axis
+fn layout_fence(fence: &str, half_height: f32, style: Style) -> Result<Layout> {
+    let scale = (2.0 * half_height / ((ASCENT + DESCENT) * style.size)).max(1.0);
+    let size = style.size * scale;
+    let mut layout = text(fence, Style { size, ..style }, false, false)?;
+    let shift = (ASCENT - DESCENT) / 2.0 * size - AXIS_HEIGHT * style.size;
+    layout.ascent -= shift;
+    layout.descent += shift;
+    for item in &mut layout.items {
+        if let Item::Text { y, .. } = item {
+            *y += shift;
+        }
+    }
+    Ok(layout)
+}
+
+fn layout_scripts(
+    mut base: Layout,
+    sub: Option<roxmltree::Node>,
+    sup: Option<roxmltree::Node>,
+    style: Style,
+) -> Result<Layout> {
+    let x = base.width + 0.03 * style.size;
+    let sub = sub.map(|sub| layout(sub, style.script())).transpose()?;
+    let sup = sup.map(|sup| layout(sup, style.script())).transpose()?;
+    let mut sub_shift = (0.2 * style.size).max(base.descent);
+    let mut sup_shift = (0.4 * style.size).max(base.ascent - 0.3 * style.size);
+    if let (Some(sub), Some(sup)) = (&sub, &sup) {
+        // keep the scripts apart
+        let gap = (sup_shift - sup.descent) - (sub.ascent - sub_shift);
+        let min_gap = 0.15 * style.size;
+        if gap < min_gap {
+            sub_shift += (min_gap - gap) / 2.0;
+            sup_shift += (min_gap - gap) / 2.0;
+        }
+    }
+    if let Some(sub) = sub {
+        base.place(sub, x, sub_shift);
+    }
+    if let Some(sup) = sup {
+        base.place(sup, x, -sup_shift);
+    }
+    base.width += 0.05 * style.size;
+    Ok(base)
+}
+
+// accents drawn high in their line like `^` and `¨`, others like arrows are drawn around the axis
+const HIGH_ACCENTS: &[&str] = &["^", "ˆ", "ˇ", "˘", "˙", "¨", "´", "`", "˜", "˚", "°", "′"];
+const BARS: &[&str] = &["‾", "¯", "_", "―", "─", "—"];
+
+/// Accents and limits centered above and below the base
+fn layout_limits(
+    base: Layout,
+    under: Option<roxmltree::Node>,
+    over: Option<roxmltree::Node>,
+    style: Style,
+) -> Result<Layout> {
+    let gap = 0.1 * style.size;
+    let is_bar = |node: roxmltree::Node| {
+        node.has_tag_name("mo") && BARS.contains(&element_text(node).as_str())
+    };
+    let is_accent = |node: roxmltree::Node| {
+        node.has_tag_name("mo")
+            && (node.attribute("accent") == Some("true") || element_text(node).chars().count() == 1)
+    };
+    let width = [under, over]
+        .into_iter()
+        .flatten()
+        .filter(|node| !is_bar(*node))
+        .map(|node| layout(node, style.script()).map(|layout| layout.width))
+        .try_fold(base.width, |width, other| {
+            other.map(|other| width.max(other))
+        })?;
+
+    let (base_ascent, base_descent) = (base.ascent, base.descent);
+    let mut stack = Layout::default();
+    stack.place(centered(base, width), 0.0, 0.0);
+    if let Some(under) = under {
+        if is_bar(under) {
+            stack.rule(0.0, base_descent + gap, width, style);
+        } else {
+            let under = layout(under, style.script())?;
+            let y = base_descent + gap + under.ascent;
+            stack.place(centered(under, width), 0.0, y);
+        }
+    }
+    if let Some(over) = over {
+        if is_bar(over) {
+            stack.rule(0.0, -(base_ascent + gap), width, style);
+        } else if is_accent(over) {
+            // the middle of the accent's glyph a little above the base
+            let high = HIGH_ACCENTS.contains(&element_text(over).as_str());
+            let accent_style = if high { style } else { style.script() };
+            let middle = if high { 0.6 } else { AXIS_HEIGHT } * accent_style.size;
+            let accent = layout(over, accent_style)?;
+            let y = -(base_ascent + gap - middle);
+            stack.place(centered(accent, width), 0.0, y);
+        } else {
+            let over = layout(over, style.script())?;
+            let y = -(base_ascent + gap + over.descent);
+            stack.place(centered(over, width), 0.0, y);
+        }
+    }
+    Ok(stack)
+}
+
+/// The layout moved to the middle of `width`
+fn centered(layout: Layout, width: f32) -> Layout {
+    let mut centered = Layout::default();
+    let x = (width - layout.width) / 2.0;
+    centered.place(layout, x, 0.0);
+    centered.width = width;
+    centered
+}
+
+fn layout_fraction(
+    numerator: roxmltree::Node,
+    denominator: roxmltree::Node,
+    line_thickness: Option<&str>,
+    style: Style,
+) -> Result<Layout> {
+    let inner = if style.display {
+        Style {
+            display: false,
+            ..style
+        }
+    } else {
+        style.script()
+    };
+    let numerator = layout(numerator, inner)?;
+    let denominator = layout(denominator, inner)?;
+    let width = numerator.width.max(denominator.width) + 0.2 * style.size;
+    let axis = -AXIS_HEIGHT * style.size;
+    let gap = if style.display { 0.15 } else { 0.1 } * style.size;
+
+    let mut fraction = Layout::default();
+    let y = axis - gap - numerator.descent;
+    fraction.place(centered(numerator, width), 0.0, y);
+    let y = axis + gap + denominator.ascent;
+    fraction.place(centered(denominator, width), 0.0, y);
+    // binomial coefficients have no bar
+    if !matches!(line_thickness, Some("0") | Some("0px") | Some("0em")) {
+        fraction.rule(0.05 * style.size, axis, width - 0.1 * style.size, style);
+    }
+    fraction.width = width;
+    Ok(fraction)
+}
+
+fn layout_root(radicand: Layout, index: Option<Layout>, style: Style) -> Layout {
+    let gap = 0.12 * style.size;
+    let sign_width = 0.5 * style.size;
+    let top = -(radicand.ascent + gap);
+    let bottom = radicand.descent;
+    let thickness = RULE_THICKNESS * style.size;
+
+    let mut root = Layout::default();
+    let x = match index {
+        // the index sits in the notch of the sign
+        Some(index) => {
+            let index_width = index.width;
+            let y = bottom - 0.5 * (bottom - top) - index.descent;
+            root.place(index, 0.0, y);
+            (index_width - 0.25 * style.size).max(0.0)
+        }
+        None => 0.0,
+    };
+    let end = x + sign_width + radicand.width + 0.05 * style.size;
+    root.items.push(Item::Polyline {
+        points: vec![
+            (x, bottom - 0.4 * (bottom - top)),
+            (x + 0.12 * style.size, bottom - 0.45 * (bottom - top)),
+            (x + 0.25 * style.size, bottom),
+            (x + sign_width - 0.05 * style.size, top),
+            (end, top),
+        ],
+        thickness,
+    });
+    root.ascent = root.ascent.max(-top + thickness);
+    root.descent = root.descent.max(bottom + thickness);
+    root.place(radicand, x + sign_width, 0.0);
+    root.width = end + 0.05 * style.size;
+    root
+}
+
+/// A matrix, its cells centered in their columns and the whole table centered on the axis
+fn layout_table(node: roxmltree::Node, style: Style) -> Result<Layout> {
+    let inner = Style {
+        display: false,
+        ..style
+    };
+    let mut rows = vec![];
+    for row in node.children().filter(|n| n.has_tag_name("mtr")) {
+        let mut cells = vec![];
+        for cell in row.children().filter(|n| n.has_tag_name("mtd")) {
+            cells.push(layout_row(cell, inner)?);
+        }
+        rows.push(cells);
+    }
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let column_widths = (0..columns)
+        .map(|column| {
+            rows.iter()
+                .filter_map(|row| row.get(column).map(|cell| cell.width))
+                .fold(0.0, f32::max)
+        })
+        .collect::<Vec<_>>();
+    let column_gap = 0.8 * style.size;
+    let row_gap = 0.3 * style.size;
+
+    let mut table = Layout::default();
+    let mut y = 0.0;
+    for row in rows {
+        let ascent = row
+            .iter()
+            .map(|cell| cell.ascent)
+            .fold(ASCENT * style.size, f32::max);
+        let descent = row
+            .iter()
+            .map(|cell| cell.descent)
+            .fold(DESCENT * style.size, f32::max);
+        y += ascent;
+        let mut x = 0.0;
+        for (cell, column_width) in row.into_iter().zip(&column_widths) {
+            let cell_x = x + (column_width - cell.width) / 2.0;
+            table.place(cell, cell_x, y);
+            x += column_width + column_gap;
+        }
+        y += descent + row_gap;
+    }
+    table.width = column_widths.iter().sum::<f32>() + column_gap * (columns.max(1) - 1) as f32;
+
+    // move the table's middle onto the axis
+    let height = (y - row_gap).max(0.0);
+    let mut centered = Layout::default();
+    centered.place(table, 0.0, -(AXIS_HEIGHT * style.size + height / 2.0));
+    // the table's origin is at its top, not on a baseline
+    centered.ascent = AXIS_HEIGHT * style.size + height / 2.0;
+    centered.descent = height / 2.0 - AXIS_HEIGHT * style.size;
+    Ok(centered)
+}
+
+/// Parses lengths like `0.167em` or `3pt`, named and unknown lengths are a thin space
+fn parse_em(length: &str) -> f32 {
+    let length = length.trim();
+    let number = |suffix: &str| length.strip_suffix(suffix)?.trim().parse::<f32>().ok();
+    number("em")
+        .or_else(|| number("ex").map(|ex| ex * 0.45))
+        .or_else(|| number("pt").map(|pt| pt / 10.0))
+        .or_else(|| number("px").map(|px| px / 16.0))
+        .or_else(|| length.parse().ok())
+        .unwrap_or(0.167)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_renderer::ImageFormat;
+
+    #[test]
+    fn renders_mathml_to_png() -> Result<()> {
+        let inline = render_mathml(
+            r#"<math xmlns="http://www.w3.org/1998/Math/MathML" display="inline"><mi>E</mi><mo>=</mo><mi>m</mi><msup><mi>c</mi><mn>2</mn></msup></math>"#,
+        )?;
+        assert_eq!(ImageFormat::sniff(&inline.png), Some(ImageFormat::Png));
+        assert!(inline.width > 2.0 && inline.width < 6.0);
+
+        let fraction = render_mathml(
+            r#"<math display="block"><mfrac><mrow><mi>a</mi><mo>+</mo><mi>b</mi></mrow><msqrt><mn>2</mn></msqrt></mfrac></math>"#,
+        )?;
+        // numerator and denominator are stacked
+        assert!(fraction.height > 1.5 * inline.height);
+        assert!(fraction.depth > inline.depth);
+
+        Ok(())
+    }
+
+    #[test]
+    fn lays_out_tables_around_the_axis() -> Result<()> {
+        let document = roxmltree::Document::parse(
+            "<mtable><mtr><mtd><mn>1</mn></mtd><mtd><mn>0</mn></mtd></mtr><mtr><mtd><mn>0</mn></mtd><mtd><mn>1</mn></mtd></mtr></mtable>",
+        )?;
+        let table = layout(
+            document.root_element(),
+            Style {
+                size: 1.0,
+                display: true,
+            },
+        )?;
+
+        let middle = (table.ascent - table.descent) / 2.0;
+        assert!((middle - AXIS_HEIGHT).abs() < 1e-4);
+        assert_eq!(table.items.len(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn parses_lengths_in_em() {
+        assert_eq!(parse_em("1em"), 1.0);
+        assert_eq!(parse_em(" 0.5em "), 0.5);
+        assert_eq!(parse_em("10pt"), 1.0);
+        assert_eq!(parse_em("thinmathspace"), 0.167);
+    }
+}
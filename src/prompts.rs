@@ -0,0 +1,171 @@
+use anyhow::{Context, Result};
+use handlebars::Handlebars;
+use lesswrong_api::Post;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+
+/// Number of key takeaways asked for per post
+pub const MAX_TAKEAWAYS: usize = 5;
+
+/// Range of review questions asked for per post
+pub const MIN_REVIEW_QUESTIONS: usize = 3;
+pub const MAX_REVIEW_QUESTIONS: usize = 5;
+
+/// System prompts rendered from Handlebars templates, so they can be tuned without recompiling.
+///
+/// Available variables: `title`, `author`, `word_budget`, for the comments prompt `comment_count`
+/// for the prompt combining partial summaries of long posts `kind` and `part_count`,
+/// for the key takeaways prompt `max_takeaways` and for the review questions prompt `min_questions` and `max_questions`.
+/// All prompts get `language`, the language to answer in, unset for English.
+pub struct Prompts {
+    directory: PathBuf,
+    /// approximate length of a summary in words
+    word_budget: usize,
+    /// English name of the language of the book, `None` for English
+    language: Option<String>,
+}
+
+impl Default for Prompts {
+    fn default() -> Self {
+        Self::new("epub_resources/prompts", 200)
+    }
+}
+
+impl Prompts {
+    pub fn new(directory: impl Into<PathBuf>, word_budget: usize) -> Self {
+        Self {
+            directory: directory.into(),
+            word_budget,
+            language: None,
+        }
+    }
+
+    /// Asks for summaries in the language, `None` for English
+    pub fn set_language(&mut self, language: Option<String>) {
+        self.language = language;
+    }
+
+    /// Approximate length of a summary in words
+    pub fn word_budget(&self) -> usize {
+        self.word_budget
+    }
+
+    pub fn summarize_post(&self, post: &Post) -> Result<String> {
+        self.render(
+            "summarize_post.hbs",
+            &json!({ "title": post.title, "author": post.author, "word_budget": self.word_budget }),
+        )
+    }
+
+    pub fn summarize_comments(&self, post: &Post, comment_count: usize) -> Result<String> {
+        self.render(
+            "summarize_comments.hbs",
+            &json!({ "title": post.title, "author": post.author, "word_budget": self.word_budget, "comment_count": comment_count }),
+        )
+    }
+
+    /// Prompt to combine the partial summaries of a post or its comments that didn't fit into the model's context.
+    /// `kind` describes what was summarized, e.g. "text" or "comment discussion"
+    pub fn combine_summaries(&self, post: &Post, kind: &str, part_count: usize) -> Result<String> {
+        self.render(
+            "combine_summaries.hbs",
+            &json!({ "title": post.title, "author": post.author, "word_budget": self.word_budget, "kind": kind, "part_count": part_count }),
+        )
+    }
+
+    /// Prompt for the key takeaways and glossary terms of a post, answered with JSON
+    pub fn key_takeaways(&self, post: &Post) -> Result<String> {
+        self.render(
+            "key_takeaways.hbs",
+            &json!({ "title": post.title, "author": post.author, "word_budget": self.word_budget, "max_takeaways": MAX_TAKEAWAYS }),
+        )
+    }
+
+    /// Prompt for review questions with answers about a post, answered with JSON
+    pub fn review_questions(&self, post: &Post) -> Result<String> {
+        self.render(
+            "review_questions.hbs",
+            &json!({ "title": post.title, "author": post.author, "word_budget": self.word_budget, "min_questions": MIN_REVIEW_QUESTIONS, "max_questions": MAX_REVIEW_QUESTIONS }),
+        )
+    }
+
+    /// Prompt to translate a part of the post into the book's language
+    pub fn translate(&self, post: &Post) -> Result<String> {
+        let language = self
+            .language
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("No language to translate {} into", post.title))?;
+        self.render(
+            "translate.hbs",
+            &json!({ "title": post.title, "author": post.author, "word_budget": self.word_budget, "language": language }),
+        )
+    }
+
+    fn render(&self, template_name: &str, data: &Value) -> Result<String> {
+        let path = self.directory.join(template_name);
+        let template = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read prompt template {:?}", path))?;
+
+        let mut data = data.clone();
+        if let (Some(language), Some(data)) = (&self.language, data.as_object_mut()) {
+            data.entry("language").or_insert(json!(language));
+        }
+
+        let mut reg = Handlebars::new();
+        // prompts are plain text, not HTML
+        reg.register_escape_fn(handlebars::no_escape);
+        let prompt = reg
+            .render_template(&template, &data)
+            .with_context(|| format!("Failed to render prompt template {:?}", path))?;
+        Ok(prompt.trim().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_default_prompts() -> Result<()> {
+        let prompts = Prompts::new("epub_resources/prompts", 150);
+        let post = Post {
+            title: "Making Beliefs Pay Rent".to_string(),
+            author: "Eliezer Yudkowsky".to_string(),
+            ..Post::default()
+        };
+
+        let post_prompt = prompts.summarize_post(&post)?;
+        assert!(post_prompt.contains("keeping it under about 150 words"));
+        assert!(!post_prompt.contains("{{"));
+
+        let comments_prompt = prompts.summarize_comments(&post, 42)?;
+        assert!(comments_prompt.contains("THE DISCUSSION IN THE COMMENTS"));
+        assert!(!comments_prompt.contains("{{"));
+
+        let combine_prompt = prompts.combine_summaries(&post, "comment discussion", 3)?;
+        assert!(combine_prompt.contains(
+            "The comment discussion of the LessWrong article \"Making Beliefs Pay Rent\" by Eliezer Yudkowsky"
+        ));
+        assert!(combine_prompt.contains("split into 3 parts"));
+
+        let takeaways_prompt = prompts.key_takeaways(&post)?;
+        assert!(takeaways_prompt.contains("at most 5 key takeaways"));
+        assert!(!takeaways_prompt.contains("{{"));
+
+        let questions_prompt = prompts.review_questions(&post)?;
+        assert!(questions_prompt.contains("write 3 to 5 review questions"));
+        assert!(!questions_prompt.contains("{{"));
+        assert!(prompts.translate(&post).is_err());
+
+        let mut german = Prompts::new("epub_resources/prompts", 150);
+        german.set_language(Some("German".to_string()));
+        assert!(german
+            .summarize_post(&post)?
+            .ends_with("Write the summary in German."));
+        assert!(german.translate(&post)?.contains("into German"));
+        // English prompts are unchanged, keeping their cached summaries
+        assert!(!post_prompt.contains("Write the summary in"));
+
+        Ok(())
+    }
+}
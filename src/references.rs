@@ -0,0 +1,242 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::OnceLock,
+};
+
+use lesswrong_api::Post;
+use lol_html::{element, rewrite_str, RewriteStrSettings};
+use regex::Regex;
+
+/// A post the book's posts link to without being part of the book, with its summary for the appendix
+pub struct ReferencedPost {
+    pub post: Post,
+    pub summary: String,
+}
+
+/// A link to a LessWrong post
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PostLink {
+    pub post_id: String,
+    pub anchor: Option<String>,
+}
+
+/// Parses links to LessWrong posts like `https://www.lesswrong.com/posts/<id>/<slug>` or `/s/<sequence>/p/<id>`,
+/// absolute or relative. `None` for any other link
+pub fn parse_post_link(href: &str) -> Option<PostLink> {
+    static POST_LINK_RE: OnceLock<Regex> = OnceLock::new();
+    let re = POST_LINK_RE.get_or_init(|| {
+        Regex::new(
+            r"^(?:(?:(?:https?:)?//)?(?:www\.)?lesswrong\.com)?/(?:posts|s/[A-Za-z0-9]+/p)/([A-Za-z0-9]+)(?:/[^?#]*)?(?:\?[^#]*)?(?:#(.*))?$",
+        )
+        .unwrap()
+    });
+    let captures = re.captures(href.trim())?;
+    Some(PostLink {
+        post_id: captures[1].to_string(),
+        anchor: captures
+            .get(2)
+            .map(|m| m.as_str().to_string())
+            .filter(|anchor| !anchor.is_empty()),
+    })
+}
+
+/// The posts the HTML links to, each once in the order of their first link
+pub fn linked_post_ids(html: &str) -> Vec<String> {
+    let mut post_ids: Vec<String> = vec![];
+    let element_content_handlers = vec![element!("a[href]", |el| {
+        if let Some(link) = parse_post_link(&el.get_attribute("href").unwrap()) {
+            if !post_ids.contains(&link.post_id) {
+                post_ids.push(link.post_id);
+            }
+        }
+        Ok(())
+    })];
+    rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers,
+            ..RewriteStrSettings::new()
+        },
+    )
+    .unwrap();
+    post_ids
+}
+
+/// The `max_posts` posts most often linked by the book's posts, excluding the book's posts themselves.
+/// `linked` holds the linked post ids of every post of the book, each citing post counts once. Ties keep the reading order
+pub fn most_referenced(
+    linked: &[Vec<String>],
+    book_post_ids: &HashSet<&str>,
+    max_posts: usize,
+) -> Vec<String> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    let mut post_ids: Vec<&str> = vec![];
+    for ids in linked {
+        let mut citing: HashSet<&str> = HashSet::new();
+        for post_id in ids {
+            if book_post_ids.contains(post_id.as_str()) || !citing.insert(post_id) {
+                continue;
+            }
+            let count = counts.entry(post_id).or_default();
+            if *count == 0 {
+                post_ids.push(post_id);
+            }
+            *count += 1;
+        }
+    }
+    post_ids.sort_by_key(|id| std::cmp::Reverse(counts[id]));
+    post_ids
+        .into_iter()
+        .take(max_posts)
+        .map(String::from)
+        .collect()
+}
+
+/// Gives the headings of a post converted from markdown the ids LessWrong gives them, the heading's letters, digits
+/// and spaces with `_` for the spaces, numbered from the second heading with the same text on. Keeps the anchors of
+/// links to the post working in the book
+pub fn add_heading_ids(html: &str) -> String {
+    static HEADING_RE: OnceLock<Regex> = OnceLock::new();
+    static MARKUP_RE: OnceLock<Regex> = OnceLock::new();
+    let heading_re =
+        HEADING_RE.get_or_init(|| Regex::new(r"(?s)<h([1-6])>(.*?)</h[1-6]>").unwrap());
+    let markup_re = MARKUP_RE.get_or_init(|| Regex::new(r"<[^>]*>|&[#a-zA-Z0-9]+;").unwrap());
+    let mut used: HashMap<String, usize> = HashMap::new();
+
+    heading_re
+        .replace_all(html, |caps: &regex::Captures| {
+            let text = markup_re.replace_all(&caps[2], "");
+            let base = text
+                .trim()
+                .chars()
+                .filter(|c| c.is_alphanumeric() || *c == ' ')
+                .map(|c| if c == ' ' { '_' } else { c })
+                .collect::<String>();
+            if base.is_empty() {
+                return caps[0].to_string();
+            }
+            let count = used.entry(base.clone()).or_default();
+            *count += 1;
+            let id = match *count {
+                1 => base,
+                n => format!("{}{}", base, n),
+            };
+            format!("<h{} id=\"{}\">{}</h{}>", &caps[1], id, &caps[2], &caps[1])
+        })
+        .into_owned()
+}
+
+/// Points links to posts that are part of the book to their place in it. `targets` maps post ids to their href in the book.
+/// Anchors are kept for links to chapters, whose headings have LessWrong's ids (see `add_heading_ids`), and dropped for
+/// targets with an anchor of their own. All other links are left alone
+pub fn rewrite_post_links(html: &str, targets: &HashMap<String, String>) -> String {
+    if targets.is_empty() {
+        return html.to_string();
+    }
+    let element_content_handlers = vec![element!("a[href]", |el| {
+        let Some(link) = parse_post_link(&el.get_attribute("href").unwrap()) else {
+            return Ok(());
+        };
+        if let Some(target) = targets.get(&link.post_id) {
+            match link.anchor {
+                Some(anchor) if !target.contains('#') => {
+                    el.set_attribute("href", &format!("{}#{}", target, anchor))?
+                }
+                _ => el.set_attribute("href", target)?,
+            }
+        }
+        Ok(())
+    })];
+    rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers,
+            ..RewriteStrSettings::new()
+        },
+    )
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_lesswrong_post_links() {
+        let link = |post_id: &str, anchor: Option<&str>| {
+            Some(PostLink {
+                post_id: post_id.into(),
+                anchor: anchor.map(Into::into),
+            })
+        };
+        assert_eq!(
+            parse_post_link(
+                "https://www.lesswrong.com/posts/46qnWRSR7L2eyNbMA/the-lens-that-sees-its-flaws"
+            ),
+            link("46qnWRSR7L2eyNbMA", None)
+        );
+        assert_eq!(
+            parse_post_link("lesswrong.com/posts/46qnWRSR7L2eyNbMA"),
+            link("46qnWRSR7L2eyNbMA", None)
+        );
+        assert_eq!(
+            parse_post_link(
+                "https://lesswrong.com/s/5g5TkQTe9rmPS5vvM/p/46qnWRSR7L2eyNbMA#Conclusion"
+            ),
+            link("46qnWRSR7L2eyNbMA", Some("Conclusion"))
+        );
+        assert_eq!(
+            parse_post_link("/posts/46qnWRSR7L2eyNbMA/slug?commentId=abc#"),
+            link("46qnWRSR7L2eyNbMA", None)
+        );
+        assert_eq!(
+            parse_post_link("https://example.com/posts/46qnWRSR7L2eyNbMA"),
+            None
+        );
+        assert_eq!(
+            parse_post_link("https://www.lesswrong.com/tag/bayes-theorem"),
+            None
+        );
+    }
+
+    #[test]
+    fn ranks_referenced_posts_by_citing_posts() {
+        let linked = vec![
+            vec!["a".to_string(), "b".into(), "in-book".into()],
+            vec!["b".to_string(), "c".into()],
+            vec!["c".to_string(), "b".into()],
+        ];
+        let book = HashSet::from(["in-book"]);
+
+        assert_eq!(most_referenced(&linked, &book, 2), vec!["b", "c"]);
+        assert_eq!(most_referenced(&linked, &book, 5), vec!["b", "c", "a"]);
+    }
+
+    #[test]
+    fn rewrites_links_to_posts_in_the_book() {
+        let html = r##"<p><a href="https://www.lesswrong.com/posts/aaa/first#section">first</a> <a href="https://www.lesswrong.com/posts/bbb/second#intro">second</a> <a href="https://www.lesswrong.com/posts/ccc/elsewhere">elsewhere</a> <a href="https://example.com">external</a></p>"##;
+        let targets = HashMap::from([
+            ("aaa".to_string(), "first.xhtml".to_string()),
+            (
+                "bbb".to_string(),
+                "referenced-posts.xhtml#ref-second".to_string(),
+            ),
+        ]);
+
+        assert_eq!(
+            rewrite_post_links(html, &targets),
+            r##"<p><a href="first.xhtml#section">first</a> <a href="referenced-posts.xhtml#ref-second">second</a> <a href="https://www.lesswrong.com/posts/ccc/elsewhere">elsewhere</a> <a href="https://example.com">external</a></p>"##
+        );
+        assert_eq!(linked_post_ids(html), vec!["aaa", "bbb", "ccc"]);
+    }
+
+    #[test]
+    fn gives_headings_lesswrong_ids() {
+        let html = "<h1>Intro</h1><p>text</p><h2>The <em>Bayesian</em> view &amp; its limits</h2><h2>Intro</h2><h3>?</h3>";
+
+        assert_eq!(
+            add_heading_ids(html),
+            r#"<h1 id="Intro">Intro</h1><p>text</p><h2 id="The_Bayesian_view__its_limits">The <em>Bayesian</em> view &amp; its limits</h2><h2 id="Intro2">Intro</h2><h3>?</h3>"#
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use anyhow::Result;
+use std::{
+    collections::VecDeque,
+    fmt,
+    future::Future,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Error context a `Summarizer` attaches when the provider asks to wait before the next request (`Retry-After`)
+#[derive(Debug, Clone, Copy)]
+pub struct RetryAfter(pub Duration);
+
+impl fmt::Display for RetryAfter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "provider asked to retry after {:?}", self.0)
+    }
+}
+
+/// Error context a `Summarizer` attaches to a failed HTTP request, to tell transient failures from permanent ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HttpStatus(pub u16);
+
+impl HttpStatus {
+    /// Rate limits, timeouts and server errors may succeed later, other client errors like a bad request or key won't
+    pub fn is_retryable(&self) -> bool {
+        matches!(self.0, 408 | 409 | 425 | 429) || self.0 >= 500
+    }
+}
+
+impl fmt::Display for HttpStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HTTP status {}", self.0)
+    }
+}
+
+/// Whether the operation that failed with `error` is worth retrying. Errors without an `HttpStatus`,
+/// e.g. network errors or rejected responses, are retried
+fn is_retryable(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<HttpStatus>()
+        .is_none_or(HttpStatus::is_retryable)
+}
+
+/// Retries with exponential backoff: `base_delay`, `2 * base_delay`, `4 * base_delay`, ... capped at `max_delay`
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `attempt + 1`
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay)
+    }
+
+    /// Runs `operation` until it succeeds or the retries are used up, returning the last error.
+    /// A `RetryAfter` in the error takes precedence over the backoff delay, errors with a non-retryable `HttpStatus` are returned immediately.
+    pub async fn retry<T, F, Fut>(&self, description: &str, mut operation: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match operation().await {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt >= self.max_retries || !is_retryable(&e) => return Err(e),
+                Err(e) => {
+                    let delay = match e.downcast_ref::<RetryAfter>() {
+                        Some(RetryAfter(retry_after)) => (*retry_after).min(self.max_delay),
+                        None => self.delay(attempt),
+                    };
+                    println!(
+                        "{} failed ({:#}), retrying in {:?} ({}/{})",
+                        description,
+                        e,
+                        delay,
+                        attempt + 1,
+                        self.max_retries
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Limits the number of requests started within any minute
+pub struct RateLimiter {
+    requests_per_minute: usize,
+    sent: Mutex<VecDeque<Instant>>,
+}
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+impl RateLimiter {
+    pub fn new(requests_per_minute: usize) -> Self {
+        Self {
+            requests_per_minute: requests_per_minute.max(1),
+            sent: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Waits until another request can be sent and counts it
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut sent = self.sent.lock().unwrap();
+                let now = Instant::now();
+                while sent
+                    .front()
+                    .is_some_and(|t| now.duration_since(*t) >= MINUTE)
+                {
+                    sent.pop_front();
+                }
+                match sent.front() {
+                    Some(oldest) if sent.len() >= self.requests_per_minute => {
+                        MINUTE - now.duration_since(*oldest)
+                    }
+                    _ => {
+                        sent.push_back(now);
+                        return;
+                    }
+                }
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    fn no_delay_policy(max_retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn backs_off_exponentially_up_to_the_max_delay() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            base_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(60),
+        };
+
+        assert_eq!(policy.delay(0), Duration::from_secs(2));
+        assert_eq!(policy.delay(1), Duration::from_secs(4));
+        assert_eq!(policy.delay(3), Duration::from_secs(16));
+        assert_eq!(policy.delay(5), Duration::from_secs(60));
+        assert_eq!(policy.delay(40), Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn retries_until_success() -> Result<()> {
+        let mut calls = 0;
+        let result = no_delay_policy(3)
+            .retry("test", || {
+                calls += 1;
+                let calls = calls;
+                async move {
+                    if calls < 3 {
+                        Err(anyhow!("failure {}", calls))
+                    } else {
+                        Ok(calls)
+                    }
+                }
+            })
+            .await?;
+
+        assert_eq!(result, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let mut calls = 0;
+        let result: Result<()> = no_delay_policy(2)
+            .retry("test", || {
+                calls += 1;
+                async {
+                    Err(anyhow!(RetryAfter(Duration::from_secs(3600))).context("rate limited"))
+                }
+            })
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(calls, 3);
+    }
+
+    #[tokio::test]
+    async fn fails_fast_on_client_errors() {
+        let mut calls = 0;
+        let result: Result<()> = no_delay_policy(3)
+            .retry("test", || {
+                calls += 1;
+                async { Err(anyhow!(HttpStatus(401)).context("unauthorized")) }
+            })
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+        assert!(HttpStatus(429).is_retryable());
+        assert!(HttpStatus(503).is_retryable());
+        assert!(!HttpStatus(404).is_retryable());
+    }
+
+    #[tokio::test]
+    async fn rate_limiter_allows_requests_within_the_limit() {
+        let limiter = RateLimiter::new(3);
+        for _ in 0..3 {
+            tokio::time::timeout(Duration::from_millis(100), limiter.acquire())
+                .await
+                .expect("request within the limit was delayed");
+        }
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), limiter.acquire())
+                .await
+                .is_err()
+        );
+    }
+}
@@ -1,40 +1,279 @@
-use std::collections::HashMap;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
 
 use lesswrong_api::Comment;
 
-fn sort_children_recursive(
-    parent: Option<String>,
-    results: &mut Vec<Comment>,
-    max_comments: &usize,
-    comments: &HashMap<String, Comment>,
-) {
-    // get all direct children and sort them by score descending
-    let mut children = comments
-        .values()
-        .filter(|c| c.parent_comment_id == parent)
-        .cloned()
-        .collect::<Vec<_>>();
-    children.sort_by(|a, b| b.base_score.partial_cmp(&a.base_score).unwrap());
-
-    // pick up child that is being processed, then recurse (depth-first)
-    for child in children.drain(..) {
-        if results.len() >= *max_comments {
-            return;
+use crate::{
+    chunking::estimate_tokens,
+    comment_tree::{CommentTree, TreeNode},
+};
+
+/// A comment with its depth in the thread, 0 for top-level comments
+#[derive(Debug, Clone)]
+pub struct ThreadedComment {
+    pub comment: Comment,
+    pub depth: usize,
+}
+
+/// Order in which the comment tree is walked, deciding which comments are kept when a limit is reached
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Traversal {
+    /// every comment is followed by its replies, favoring deep discussions of the best comments
+    #[default]
+    DepthFirst,
+    /// all top-level comments first, then their replies level by level, favoring a broad overview
+    BreadthFirst,
+}
+
+impl FromStr for Traversal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "depth-first" => Ok(Self::DepthFirst),
+            "breadth-first" => Ok(Self::BreadthFirst),
+            _ => Err(format!(
+                "unknown comment order \"{}\", expected depth-first or breadth-first",
+                s
+            )),
+        }
+    }
+}
+
+/// How replies to the same comment are ranked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Ranking {
+    /// highest karma first
+    #[default]
+    Score,
+    /// karma discounted by the age of the comment relative to the newest one, so recent discussion rises
+    Recent,
+}
+
+impl FromStr for Ranking {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "score" => Ok(Self::Score),
+            "recent" => Ok(Self::Recent),
+            _ => Err(format!(
+                "unknown comment ranking \"{}\", expected score or recent",
+                s
+            )),
+        }
+    }
+}
+
+// how fast old comments sink with `Ranking::Recent`, as in Hacker News' ranking
+const RECENCY_GRAVITY: f64 = 1.5;
+
+/// Which comments of a post are summarized or rendered, and in which order.
+/// Comments filtered out by `min_karma` are dropped with all their replies, which would lack context on their own.
+/// The comments skipped by `exclude_post_author` are only left out, their replies take their place in the thread.
+#[derive(Debug, Clone)]
+pub struct CommentSelector {
+    pub traversal: Traversal,
+    pub ranking: Ranking,
+    /// total number of comments
+    pub max_comments: Option<usize>,
+    /// number of top-level comments with their replies
+    pub max_threads: Option<usize>,
+    /// number of comments per thread, including the top-level comment
+    pub max_per_thread: Option<usize>,
+    pub min_karma: Option<f64>,
+    /// skip the comments of the post's author, e.g. when they mostly repeat the post. Replies to them are kept
+    pub exclude_post_author: bool,
+    /// total estimated tokens of the comments' markdown
+    pub max_tokens: Option<usize>,
+}
+
+impl Default for CommentSelector {
+    /// The best 100 comments by score, depth-first
+    fn default() -> Self {
+        Self {
+            traversal: Traversal::default(),
+            ranking: Ranking::default(),
+            max_comments: Some(100),
+            max_threads: None,
+            max_per_thread: None,
+            min_karma: None,
+            exclude_post_author: false,
+            max_tokens: None,
         }
-        let child_id = child.id.clone();
-        results.push(child);
-        sort_children_recursive(Some(child_id), results, max_comments, comments);
     }
 }
 
+impl CommentSelector {
+    fn rank(&self, comments: &HashMap<String, Comment>) -> impl Fn(&Comment) -> f64 {
+        let ranking = self.ranking;
+        let newest = comments.values().map(|c| c.date).max().unwrap_or_default();
+        move |comment: &Comment| match ranking {
+            Ranking::Score => comment.base_score,
+            Ranking::Recent => {
+                let age_days = (newest - comment.date).num_seconds().max(0) as f64 / 86_400.0;
+                comment.base_score / (age_days + 2.0).powf(RECENCY_GRAVITY)
+            }
+        }
+    }
+
+    /// The comments without the post author's if `exclude_post_author` is set. Replies to the author's comments
+    /// are moved up to the closest ancestor that isn't the author's, or become top-level comments
+    fn visible_comments<'a>(
+        &self,
+        comments: &'a HashMap<String, Comment>,
+        post_author: &str,
+    ) -> Cow<'a, HashMap<String, Comment>> {
+        if !self.exclude_post_author {
+            return Cow::Borrowed(comments);
+        }
+        let by_author = |id: &String| {
+            comments
+                .get(id)
+                .filter(|parent| parent.author == post_author)
+        };
+        Cow::Owned(
+            comments
+                .values()
+                .filter(|comment| comment.author != post_author)
+                .map(|comment| {
+                    let mut comment = comment.clone();
+                    // bounded, in case the parents form a cycle
+                    for _ in 0..comments.len() {
+                        match comment.parent_comment_id.as_ref().and_then(by_author) {
+                            Some(parent) => {
+                                comment.parent_comment_id = parent.parent_comment_id.clone()
+                            }
+                            None => break,
+                        }
+                    }
+                    (comment.id.clone(), comment)
+                })
+                .collect(),
+        )
+    }
+
+    fn tree<'a>(&self, comments: &'a HashMap<String, Comment>) -> CommentTree<'a> {
+        let mut tree = CommentTree::new(comments.values());
+        tree.sort_by_rank(self.rank(comments));
+        tree
+    }
+
+    /// The selected comments in traversal order, with the top-level comment of their thread
+    fn select_nodes<'a>(&self, tree: &CommentTree<'a>) -> Vec<TreeNode<'a>> {
+        let nodes = match self.traversal {
+            Traversal::DepthFirst => tree.depth_first(),
+            Traversal::BreadthFirst => tree.breadth_first(),
+        };
+        let mut dropped: HashSet<&str> = HashSet::new();
+        let mut threads: HashSet<&str> = HashSet::new();
+        let mut per_thread: HashMap<&str, usize> = HashMap::new();
+        let mut tokens = 0;
+        let mut selected = vec![];
+
+        for node in nodes {
+            let comment = node.comment;
+            let root = node.root.id.as_str();
+            let parent_dropped = comment
+                .parent_comment_id
+                .as_ref()
+                .is_some_and(|parent| dropped.contains(parent.as_str()));
+            let filtered = self.min_karma.is_some_and(|min| comment.base_score < min);
+            if parent_dropped || filtered {
+                dropped.insert(&comment.id);
+                continue;
+            }
+
+            if self.max_comments.is_some_and(|max| selected.len() >= max) {
+                break;
+            }
+            let new_thread = !threads.contains(root);
+            let thread_count = per_thread.get(root).copied().unwrap_or_default();
+            let comment_tokens = estimate_tokens(&comment.content_markdown);
+            if (new_thread && self.max_threads.is_some_and(|max| threads.len() >= max))
+                || self.max_per_thread.is_some_and(|max| thread_count >= max)
+                || self
+                    .max_tokens
+                    .is_some_and(|max| tokens + comment_tokens > max)
+            {
+                dropped.insert(&comment.id);
+                continue;
+            }
+            threads.insert(root);
+            per_thread.insert(root, thread_count + 1);
+            tokens += comment_tokens;
+            selected.push(node);
+        }
+        selected
+    }
+
+    /// The selected comments in traversal order
+    pub fn select(
+        &self,
+        comments: &HashMap<String, Comment>,
+        post_author: &str,
+    ) -> Vec<ThreadedComment> {
+        let comments = self.visible_comments(comments, post_author);
+        self.select_nodes(&self.tree(&comments))
+            .into_iter()
+            .map(|node| ThreadedComment {
+                comment: node.comment.clone(),
+                depth: node.depth,
+            })
+            .collect()
+    }
+
+    /// The selected comments grouped by thread, each thread in reading order: every comment followed by its replies
+    pub fn select_threads(
+        &self,
+        comments: &HashMap<String, Comment>,
+        post_author: &str,
+    ) -> Vec<Vec<ThreadedComment>> {
+        let comments = self.visible_comments(comments, post_author);
+        let tree = self.tree(&comments);
+        let selected = self.select_nodes(&tree);
+        let selected_ids = selected
+            .iter()
+            .map(|node| node.comment.id.as_str())
+            .collect::<HashSet<_>>();
+
+        // the selection keeps every selected reply's parent, so the selected comments of the depth-first walk are complete threads
+        let mut threads: HashMap<&str, Vec<ThreadedComment>> = HashMap::new();
+        for node in tree.depth_first() {
+            if selected_ids.contains(node.comment.id.as_str()) {
+                threads
+                    .entry(node.root.id.as_str())
+                    .or_default()
+                    .push(ThreadedComment {
+                        comment: node.comment.clone(),
+                        depth: node.depth,
+                    });
+            }
+        }
+        selected
+            .iter()
+            .filter(|node| node.depth == 0)
+            .filter_map(|node| threads.remove(node.comment.id.as_str()))
+            .collect()
+    }
+}
+
+/// The best `max_comments` comments by score, depth-first
 pub fn sort_comments_by_score_depth_first(
     comments: &HashMap<String, Comment>,
     max_comments: usize,
 ) -> Vec<Comment> {
-    let max_comments = std::cmp::min(max_comments, comments.len());
-    let mut results = Vec::with_capacity(max_comments);
-    sort_children_recursive(None, &mut results, &max_comments, comments);
-    results
+    CommentSelector {
+        max_comments: Some(max_comments),
+        ..CommentSelector::default()
+    }
+    .select(comments, "")
+    .into_iter()
+    .map(|c| c.comment)
+    .collect()
 }
 
 #[cfg(test)]
@@ -46,6 +285,65 @@ mod tests {
         matching == a.len() && matching == b.len()
     }
 
+    fn test_comments() -> HashMap<String, Comment> {
+        let mut comments = HashMap::new();
+        comments.insert(
+            "a".into(),
+            Comment {
+                id: "a".into(),
+                parent_comment_id: None,
+                base_score: 1.0,
+                ..Default::default()
+            },
+        );
+        comments.insert(
+            "b".into(),
+            Comment {
+                id: "b".into(),
+                parent_comment_id: None,
+                base_score: 2.0,
+                ..Default::default()
+            },
+        );
+        comments.insert(
+            "aa".into(),
+            Comment {
+                id: "aa".into(),
+                parent_comment_id: Some("a".into()),
+                base_score: 100.0,
+                ..Default::default()
+            },
+        );
+        comments.insert(
+            "ab".into(),
+            Comment {
+                id: "ab".into(),
+                parent_comment_id: Some("a".into()),
+                base_score: 101.0,
+                ..Default::default()
+            },
+        );
+        comments.insert(
+            "ba".into(),
+            Comment {
+                id: "ba".into(),
+                parent_comment_id: Some("b".into()),
+                base_score: 10.0,
+                ..Default::default()
+            },
+        );
+        comments.insert(
+            "bb".into(),
+            Comment {
+                id: "bb".into(),
+                parent_comment_id: Some("b".into()),
+                base_score: 11.0,
+                ..Default::default()
+            },
+        );
+        comments
+    }
+
     #[test]
     fn test_sort_comments_by_score_depth_first() {
         let mut comments = HashMap::new();
@@ -117,4 +415,107 @@ mod tests {
             ],
         ));
     }
+
+    #[test]
+    fn test_top_comment_threads() {
+        let mut comments = test_comments();
+        comments.insert(
+            "bba".into(),
+            Comment {
+                id: "bba".into(),
+                parent_comment_id: Some("bb".into()),
+                base_score: 1.0,
+                ..Default::default()
+            },
+        );
+
+        let selector = CommentSelector {
+            max_comments: None,
+            max_threads: Some(1),
+            max_per_thread: Some(3),
+            ..CommentSelector::default()
+        };
+        let threads = selector.select_threads(&comments, "");
+        assert_eq!(threads.len(), 1);
+        let thread = threads[0]
+            .iter()
+            .map(|c| (c.comment.id.as_str(), c.depth))
+            .collect::<Vec<_>>();
+        assert_eq!(thread, vec![("b", 0), ("bb", 1), ("bba", 2)]);
+
+        let all_threads = CommentSelector {
+            max_threads: Some(5),
+            ..selector
+        };
+        assert_eq!(all_threads.select_threads(&comments, "").len(), 2);
+    }
+
+    fn ids(comments: &[ThreadedComment]) -> Vec<&str> {
+        comments.iter().map(|c| c.comment.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_selection_strategies() {
+        let mut comments = test_comments();
+        for (id, author) in [("b", "post author"), ("aa", "post author")] {
+            comments.get_mut(id).unwrap().author = author.to_string();
+        }
+        let select = |selector: CommentSelector| {
+            ids(&selector.select(&comments, "post author"))
+                .into_iter()
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        let breadth_first = CommentSelector {
+            traversal: Traversal::BreadthFirst,
+            ..CommentSelector::default()
+        };
+        assert_eq!(select(breadth_first), ["b", "a", "bb", "ba", "ab", "aa"]);
+
+        // replies of dropped comments are dropped too
+        let min_karma = CommentSelector {
+            min_karma: Some(1.5),
+            ..CommentSelector::default()
+        };
+        assert_eq!(select(min_karma), ["b", "bb", "ba"]);
+
+        let exclude_post_author = CommentSelector {
+            exclude_post_author: true,
+            ..CommentSelector::default()
+        };
+        // replies to the author's comments move up in their place
+        assert_eq!(select(exclude_post_author), ["bb", "ba", "a", "ab"]);
+
+        let per_thread = CommentSelector {
+            max_per_thread: Some(2),
+            ..CommentSelector::default()
+        };
+        assert_eq!(select(per_thread), ["b", "bb", "a", "ab"]);
+    }
+
+    #[test]
+    fn test_token_budget_and_recency() {
+        let mut comments = test_comments();
+        comments.get_mut("a").unwrap().content_markdown = "word ".repeat(40);
+        let within_budget = CommentSelector {
+            max_tokens: Some(20),
+            ..CommentSelector::default()
+        }
+        .select(&comments, "");
+        // "a" (50 tokens) doesn't fit, so its replies are left out as well
+        assert_eq!(ids(&within_budget), ["b", "bb", "ba"]);
+
+        // "a" is a week newer than "b", which outweighs its lower score
+        comments.get_mut("a").unwrap().date = comments["b"].date + chrono::Duration::days(7);
+        let recent = CommentSelector {
+            ranking: Ranking::Recent,
+            max_threads: Some(1),
+            max_comments: None,
+            ..CommentSelector::default()
+        }
+        .select_threads(&comments, "");
+        assert_eq!(recent.len(), 1);
+        assert_eq!(ids(&recent[0]), ["a", "ab", "aa"]);
+    }
 }
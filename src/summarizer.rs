@@ -0,0 +1,531 @@
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use reqwest::{header::RETRY_AFTER, Client as ReqwestClient, Response};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{env, str::FromStr, time::Duration};
+
+use crate::{
+    chunking::estimate_tokens,
+    retry::{HttpStatus, RetryAfter},
+    usage::TokenUsage,
+};
+
+/// Response of a `Summarizer`
+#[derive(Debug, Clone)]
+pub struct Completion {
+    /// raw response text, including any reasoning tags the model emits
+    pub text: String,
+    /// tokens billed for the request, if the provider reports them
+    pub usage: Option<TokenUsage>,
+}
+
+/// An LLM backend that answers a system prompt and a single user message
+#[async_trait]
+pub trait Summarizer: Send + Sync {
+    /// Name of the model, e.g. for logging
+    fn model(&self) -> &str;
+
+    /// Provider and request parameters that influence the output, part of the summary cache key
+    fn parameters(&self) -> Value;
+
+    /// Size of the model's context window in tokens, prompt and response included
+    fn context_tokens(&self) -> usize;
+
+    async fn complete(
+        &self,
+        system_prompt: &str,
+        user_message: &str,
+        format: ResponseFormat,
+    ) -> Result<Completion>;
+}
+
+/// Format of the response the prompt asks for, backends supporting it constrain the model's output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseFormat {
+    /// Markdown or plain text
+    Text,
+    /// A single JSON object, the prompt describes its fields
+    Json,
+}
+
+/// Which `Summarizer` to use, configured by `AI_PROVIDER` or `--ai-provider`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AiProvider {
+    /// Any OpenAI-compatible API (openrouter, venice.ai, ...), configured by `OPENAI_KEY`, `OPENAI_BASE_URL` and `OPENAI_MODEL`
+    OpenAi,
+    /// A local Ollama server, configured by `OLLAMA_BASE_URL` and `OLLAMA_MODEL`
+    Ollama,
+    /// Deterministic summaries without any LLM, for tests and offline builds
+    Stub,
+}
+
+impl FromStr for AiProvider {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "openai" => Ok(Self::OpenAi),
+            "ollama" => Ok(Self::Ollama),
+            "stub" => Ok(Self::Stub),
+            _ => Err(format!(
+                "unknown AI provider \"{}\", expected one of: openai, ollama, stub",
+                s
+            )),
+        }
+    }
+}
+
+impl AiProvider {
+    /// Reads `AI_PROVIDER`, defaulting to the OpenAI-compatible API
+    pub fn from_env() -> Result<Self> {
+        match env::var("AI_PROVIDER") {
+            Ok(provider) => provider.parse().map_err(|e: String| anyhow!(e)),
+            Err(_) => Ok(Self::OpenAi),
+        }
+    }
+
+    pub fn create_summarizer(&self) -> Result<Box<dyn Summarizer>> {
+        Ok(match self {
+            Self::OpenAi => Box::new(OpenAiSummarizer::from_env()?),
+            Self::Ollama => Box::new(OllamaSummarizer::from_env()?),
+            Self::Stub => Box::new(StubSummarizer),
+        })
+    }
+}
+
+fn required_env(name: &str) -> Result<String> {
+    env::var(name).with_context(|| format!("{} not set", name))
+}
+
+/// Smallest context window the summaries work with: the 4096 tokens reserved for the response, the prompt
+/// and a part of the post. Smaller windows would split posts into tiny chunks and thousands of requests
+const MIN_CONTEXT_TOKENS: usize = 6_144;
+
+fn context_tokens_env(name: &str, default: usize) -> Result<usize> {
+    match env::var(name) {
+        Ok(tokens) => tokens
+            .parse()
+            .with_context(|| format!("{} is not a number of tokens", name)),
+        Err(_) => Ok(default),
+    }
+}
+
+fn check_context_tokens(tokens: usize) -> Result<usize> {
+    if tokens < MIN_CONTEXT_TOKENS {
+        return Err(anyhow!(
+            "the context window is {} tokens, the summaries need at least {} tokens",
+            tokens,
+            MIN_CONTEXT_TOKENS
+        ));
+    }
+    Ok(tokens)
+}
+
+/// Parses a `Retry-After` header, either a delay in seconds or an HTTP date
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    // a date in the past means the request can be retried right away
+    Some(
+        (date.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// The error of a request the provider answered with an error status, with the `HttpStatus` and
+/// the provider's `RetryAfter` for the `RetryPolicy`
+async fn http_error(provider: &str, response: Response) -> anyhow::Error {
+    let status = response.status();
+    let retry_after = response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| parse_retry_after(value, Utc::now()));
+    let body = response.text().await.unwrap_or_default();
+    let error = anyhow!("{} returned error: {}\n{}", provider, status, body)
+        .context(HttpStatus(status.as_u16()));
+    match retry_after {
+        Some(delay) => error.context(RetryAfter(delay)),
+        None => error,
+    }
+}
+
+/// Talks to the chat completions endpoint of any OpenAI-compatible API
+pub struct OpenAiSummarizer {
+    client: ReqwestClient,
+    key: String,
+    base_url: String,
+    model: String,
+    context_tokens: usize,
+}
+
+#[derive(Deserialize)]
+struct OpenAiMessage {
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiChoice {
+    message: OpenAiMessage,
+}
+
+#[derive(Deserialize)]
+struct OpenAiUsage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+#[derive(Deserialize)]
+struct OpenAiChatResponse {
+    choices: Vec<OpenAiChoice>,
+    usage: Option<OpenAiUsage>,
+}
+
+impl OpenAiSummarizer {
+    pub fn new(
+        key: String,
+        base_url: String,
+        model: String,
+        context_tokens: usize,
+    ) -> Result<Self> {
+        Ok(Self {
+            client: ReqwestClient::new(),
+            key,
+            base_url,
+            model,
+            context_tokens: check_context_tokens(context_tokens)?,
+        })
+    }
+
+    pub fn from_env() -> Result<Self> {
+        Self::new(
+            required_env("OPENAI_KEY")?,
+            env::var("OPENAI_BASE_URL").unwrap_or_else(|_| "https://api.openai.com/v1/".into()),
+            required_env("OPENAI_MODEL")?,
+            context_tokens_env("OPENAI_CONTEXT_TOKENS", 128_000)?,
+        )
+        .context("Invalid OPENAI_CONTEXT_TOKENS")
+    }
+}
+
+#[async_trait]
+impl Summarizer for OpenAiSummarizer {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn parameters(&self) -> Value {
+        json!({ "provider": "openai", "include_venice_system_prompt": false })
+    }
+
+    fn context_tokens(&self) -> usize {
+        self.context_tokens
+    }
+
+    async fn complete(
+        &self,
+        system_prompt: &str,
+        user_message: &str,
+        format: ResponseFormat,
+    ) -> Result<Completion> {
+        let mut body = json!({
+            "model": self.model,
+            "messages": [
+                { "role": "system", "content": system_prompt },
+                { "role": "user", "content": user_message },
+            ],
+            // venice.ai adds its own system prompt otherwise, other providers ignore this
+            "venice_parameters": { "include_venice_system_prompt": false },
+        });
+        if format == ResponseFormat::Json {
+            body["response_format"] = json!({ "type": "json_object" });
+        }
+        let response = self
+            .client
+            .post(format!(
+                "{}/chat/completions",
+                self.base_url.trim_end_matches('/')
+            ))
+            .bearer_auth(&self.key)
+            .json(&body)
+            .send()
+            .await
+            .with_context(|| format!("Failed to send request to {}", self.base_url))?;
+
+        if !response.status().is_success() {
+            return Err(http_error(&self.model, response).await);
+        }
+
+        let completion: OpenAiChatResponse = response
+            .json()
+            .await
+            .context("Failed to parse chat completion")?;
+        let choice = completion
+            .choices
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("{} returned no choices", self.model))?;
+        Ok(Completion {
+            text: choice.message.content.unwrap_or_default(),
+            usage: completion.usage.map(|usage| TokenUsage {
+                prompt_tokens: usage.prompt_tokens,
+                completion_tokens: usage.completion_tokens,
+            }),
+        })
+    }
+}
+
+/// Talks to a local Ollama server's chat endpoint
+pub struct OllamaSummarizer {
+    client: ReqwestClient,
+    base_url: String,
+    model: String,
+    context_tokens: usize,
+}
+
+#[derive(Deserialize)]
+struct OllamaMessage {
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct OllamaChatResponse {
+    message: OllamaMessage,
+    prompt_eval_count: Option<u64>,
+    eval_count: Option<u64>,
+}
+
+impl OllamaSummarizer {
+    pub fn new(base_url: String, model: String, context_tokens: usize) -> Result<Self> {
+        Ok(Self {
+            client: ReqwestClient::new(),
+            base_url,
+            model,
+            context_tokens: check_context_tokens(context_tokens)?,
+        })
+    }
+
+    pub fn from_env() -> Result<Self> {
+        Self::new(
+            env::var("OLLAMA_BASE_URL").unwrap_or_else(|_| "http://localhost:11434".into()),
+            required_env("OLLAMA_MODEL")?,
+            context_tokens_env("OLLAMA_CONTEXT_TOKENS", 8_192)?,
+        )
+        .context("Invalid OLLAMA_CONTEXT_TOKENS")
+    }
+}
+
+#[async_trait]
+impl Summarizer for OllamaSummarizer {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn parameters(&self) -> Value {
+        json!({ "provider": "ollama", "num_ctx": self.context_tokens })
+    }
+
+    fn context_tokens(&self) -> usize {
+        self.context_tokens
+    }
+
+    async fn complete(
+        &self,
+        system_prompt: &str,
+        user_message: &str,
+        format: ResponseFormat,
+    ) -> Result<Completion> {
+        let mut body = json!({
+            "model": self.model,
+            "stream": false,
+            // Ollama uses a small context window unless told otherwise
+            "options": { "num_ctx": self.context_tokens },
+            "messages": [
+                { "role": "system", "content": system_prompt },
+                { "role": "user", "content": user_message },
+            ],
+        });
+        if format == ResponseFormat::Json {
+            body["format"] = json!("json");
+        }
+        let response = self
+            .client
+            .post(format!("{}/api/chat", self.base_url.trim_end_matches('/')))
+            .json(&body)
+            .send()
+            .await
+            .context("Failed to send request to Ollama")?;
+
+        if !response.status().is_success() {
+            return Err(http_error("Ollama", response).await);
+        }
+
+        let response: OllamaChatResponse = response
+            .json()
+            .await
+            .context("Failed to parse Ollama response")?;
+        let usage = match (response.prompt_eval_count, response.eval_count) {
+            (Some(prompt_tokens), Some(completion_tokens)) => Some(TokenUsage {
+                prompt_tokens,
+                completion_tokens,
+            }),
+            _ => None,
+        };
+        Ok(Completion {
+            text: response.message.content,
+            usage,
+        })
+    }
+}
+
+/// Summarizes by quoting the beginning of the message, always giving the same output for the same input
+pub struct StubSummarizer;
+
+// number of words the stub quotes from the message
+const STUB_SUMMARY_WORDS: usize = 40;
+
+#[async_trait]
+impl Summarizer for StubSummarizer {
+    fn model(&self) -> &str {
+        "stub"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({ "provider": "stub", "words": STUB_SUMMARY_WORDS })
+    }
+
+    fn context_tokens(&self) -> usize {
+        128_000
+    }
+
+    async fn complete(
+        &self,
+        system_prompt: &str,
+        user_message: &str,
+        format: ResponseFormat,
+    ) -> Result<Completion> {
+        let words = user_message.split_whitespace().collect::<Vec<_>>();
+        let excerpt = words
+            .iter()
+            .take(STUB_SUMMARY_WORDS)
+            .copied()
+            .collect::<Vec<_>>()
+            .join(" ");
+        let ellipsis = if words.len() > STUB_SUMMARY_WORDS {
+            " …"
+        } else {
+            ""
+        };
+        // structured output (key takeaways, review questions) is valid JSON
+        let text = if format == ResponseFormat::Json {
+            json!({
+                "takeaways": [format!("Stub takeaway: {}{}", excerpt, ellipsis)],
+                "glossary": [],
+                "questions": [{ "question": "Stub question?", "answer": format!("{}{}", excerpt, ellipsis) }],
+            })
+            .to_string()
+        } else {
+            format!("*Stub summary:* {}{}", excerpt, ellipsis)
+        };
+        // nothing is billed, but estimates let offline builds exercise the usage report
+        let usage = TokenUsage {
+            prompt_tokens: (estimate_tokens(system_prompt) + estimate_tokens(user_message)) as u64,
+            completion_tokens: estimate_tokens(&text) as u64,
+        };
+        Ok(Completion {
+            text,
+            usage: Some(usage),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn stub_summarizer_is_deterministic() -> Result<()> {
+        let message = "word ".repeat(100);
+        let first = StubSummarizer
+            .complete("prompt", &message, ResponseFormat::Text)
+            .await?
+            .text;
+        let second = StubSummarizer
+            .complete("other prompt", &message, ResponseFormat::Text)
+            .await?
+            .text;
+
+        assert_eq!(first, second);
+        assert!(first.starts_with("*Stub summary:* word word"));
+        assert!(first.ends_with(" …"));
+        assert_eq!(
+            StubSummarizer
+                .complete("prompt", "short post", ResponseFormat::Text)
+                .await?
+                .text,
+            "*Stub summary:* short post"
+        );
+        let questions = StubSummarizer
+            .complete("prompt", "short post", ResponseFormat::Json)
+            .await?
+            .text;
+        assert!(serde_json::from_str::<Value>(&questions).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parses_retry_after_seconds_and_dates() {
+        let now = DateTime::parse_from_rfc2822("Wed, 21 Oct 2015 07:28:00 GMT")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        assert_eq!(
+            parse_retry_after(" 30 ", now),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:29:30 GMT", now),
+            Some(Duration::from_secs(90))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn rejects_tiny_context_windows() {
+        let ollama = |context_tokens| {
+            OllamaSummarizer::new(
+                "http://localhost:11434".into(),
+                "model".into(),
+                context_tokens,
+            )
+        };
+        assert!(ollama(4_096).is_err());
+        assert_eq!(ollama(32_000).unwrap().context_tokens(), 32_000);
+        assert!(OpenAiSummarizer::new(
+            "key".into(),
+            "https://api.openai.com/v1/".into(),
+            "model".into(),
+            MIN_CONTEXT_TOKENS - 1
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parses_providers() {
+        assert_eq!("openai".parse(), Ok(AiProvider::OpenAi));
+        assert_eq!("Ollama".parse(), Ok(AiProvider::Ollama));
+        assert_eq!("stub".parse(), Ok(AiProvider::Stub));
+        assert!("claude".parse::<AiProvider>().is_err());
+    }
+}
@@ -0,0 +1,168 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::AddAssign,
+    path::Path,
+};
+
+use crate::ai::SummaryKind;
+
+/// Tokens billed for one or more LLM requests
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+impl AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
+
+/// Price of a model in USD per million tokens
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct ModelPrice {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+/// Prices per model, loaded from a JSON file like `{ "deepseek-r1-671b": { "prompt": 0.8, "completion": 3.2 } }`
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PriceTable(HashMap<String, ModelPrice>);
+
+impl PriceTable {
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read price table {:?}", path))?;
+        serde_json::from_str(&json)
+            .with_context(|| format!("Failed to parse price table {:?}", path))
+    }
+
+    /// Estimated cost in USD, `None` if the model has no price
+    pub fn cost(&self, model: &str, usage: &TokenUsage) -> Option<f64> {
+        self.0.get(model).map(|price| {
+            (usage.prompt_tokens as f64 * price.prompt
+                + usage.completion_tokens as f64 * price.completion)
+                / 1_000_000.0
+        })
+    }
+}
+
+/// Usage of a single summary request. Requests answered from the cache keep the usage of the original request
+#[derive(Debug, Clone)]
+pub struct UsageRecord {
+    pub post_id: String,
+    pub post_title: String,
+    pub kind: SummaryKind,
+    pub usage: TokenUsage,
+    pub cached: bool,
+}
+
+#[derive(Default)]
+struct PostUsage {
+    title: String,
+    post: TokenUsage,
+    comments: TokenUsage,
+    takeaways: TokenUsage,
+    questions: TokenUsage,
+    translation: TokenUsage,
+}
+
+/// Prints the tokens used per post and summary kind, and the totals with their estimated cost.
+/// `title` distinguishes the build report from the `--dry-run` estimate
+pub fn print_report(title: &str, model: &str, records: &[UsageRecord], prices: &PriceTable) {
+    let mut posts: BTreeMap<&str, PostUsage> = BTreeMap::new();
+    let mut requested = TokenUsage::default();
+    let mut cached = TokenUsage::default();
+    for record in records {
+        let post = posts.entry(&record.post_id).or_default();
+        post.title.clone_from(&record.post_title);
+        match record.kind {
+            SummaryKind::Post => post.post += record.usage,
+            SummaryKind::Comments => post.comments += record.usage,
+            SummaryKind::Takeaways => post.takeaways += record.usage,
+            SummaryKind::Questions => post.questions += record.usage,
+            SummaryKind::Translation => post.translation += record.usage,
+        }
+        if record.cached {
+            cached += record.usage;
+        } else {
+            requested += record.usage;
+        }
+    }
+
+    println!("{} ({}):", title, model);
+    for (post_id, usage) in &posts {
+        // the optional structured outputs are only listed if used
+        let optional = [
+            ("key takeaways", usage.takeaways),
+            ("review questions", usage.questions),
+            ("translation", usage.translation),
+        ]
+        .iter()
+        .filter(|(_, usage)| usage.total_tokens() > 0)
+        .map(|(name, usage)| {
+            format!(
+                ", {} {} prompt + {} completion",
+                name, usage.prompt_tokens, usage.completion_tokens
+            )
+        })
+        .collect::<String>();
+        println!(
+            "  {} ({}): post {} prompt + {} completion, comments {} prompt + {} completion{} tokens",
+            usage.title,
+            post_id,
+            usage.post.prompt_tokens,
+            usage.post.completion_tokens,
+            usage.comments.prompt_tokens,
+            usage.comments.completion_tokens,
+            optional
+        );
+    }
+    let cost = |usage: &TokenUsage| match prices.cost(model, usage) {
+        Some(cost) => format!("${:.4}", cost),
+        None => "unknown cost, no price for this model".to_string(),
+    };
+    println!(
+        "  Requested: {} prompt + {} completion tokens ({})",
+        requested.prompt_tokens,
+        requested.completion_tokens,
+        cost(&requested)
+    );
+    if cached.total_tokens() > 0 {
+        println!(
+            "  From cache: {} prompt + {} completion tokens ({} saved)",
+            cached.prompt_tokens,
+            cached.completion_tokens,
+            cost(&cached)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_cost_from_price_table() -> Result<()> {
+        let prices: PriceTable =
+            serde_json::from_str(r#"{ "model-a": { "prompt": 1.0, "completion": 4.0 } }"#)?;
+        let usage = TokenUsage {
+            prompt_tokens: 2_000_000,
+            completion_tokens: 500_000,
+        };
+
+        assert_eq!(prices.cost("model-a", &usage), Some(4.0));
+        assert_eq!(prices.cost("model-b", &usage), None);
+        Ok(())
+    }
+}